@@ -0,0 +1,122 @@
+//! Matching on bytes that need not be UTF-8, such as binary logs.
+//!
+//! Valid UTF-8 in the input is read as the chars it encodes, so patterns keep their Unicode
+//! meaning. Every byte that is not part of a valid sequence is read on its own as U+FFFD, which
+//! `.` and negated classes match but no literal or Unicode class does, apart from U+FFFD itself.
+//! There is no way to match one such byte in particular: `\xFF` is the char U+00FF, which only
+//! matches its UTF-8 encoding `C3 BF`, not the byte `FF`.
+//! Offsets are byte offsets into the input.
+
+use crate::engine::evaluator::group_range;
+use crate::helper::DynError;
+use crate::regex::Searcher;
+use std::ops::Range;
+
+/// A compiled regular expression that matches on `&[u8]`.
+#[derive(Debug)]
+pub struct Regex {
+    inner: crate::Regex,
+}
+
+impl Regex {
+    pub fn new(expr: &str) -> Result<Regex, DynError> {
+        Ok(crate::Regex::new(expr)?.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.inner.as_str()
+    }
+
+    pub fn is_match(&self, line: &[u8]) -> bool {
+        self.inner.search(line, 0).is_some()
+    }
+
+    /// Returns the byte range of the match in `line`.
+    pub fn find(&self, line: &[u8]) -> Option<Range<usize>> {
+        let slots = self.inner.search(line, 0)?;
+        group_range(&slots, 0)
+    }
+
+    /// Returns an iterator over the byte ranges of the successive non-overlapping matches.
+    pub fn find_iter<'r, 'h>(&'r self, line: &'h [u8]) -> Matches<'r, 'h> {
+        Matches {
+            searcher: Searcher::new(&self.inner, line),
+        }
+    }
+}
+
+/// Reuses a program built with [`crate::RegexBuilder`] to match on bytes.
+impl From<crate::Regex> for Regex {
+    fn from(inner: crate::Regex) -> Self {
+        Self { inner }
+    }
+}
+
+/// An iterator over the byte ranges of successive non-overlapping matches in bytes.
+pub struct Matches<'r, 'h> {
+    searcher: Searcher<'r, 'h, [u8]>,
+}
+
+impl Iterator for Matches<'_, '_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Range<usize>> {
+        let slots = self.searcher.next_slots()?;
+        group_range(&slots, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MatchKind, RegexBuilder};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("ab", b"\xFFab\x00", Some(1..3))]
+    #[case("a.c", b"a\x80c", Some(0..3))]
+    #[case(r"\d+", b"\xC3\x28 42", Some(3..5))]
+    #[case(r"\bok\b", b"\xFEok\xFE", Some(1..3))]
+    #[case("x", b"\xFF\xFE", None)]
+    fn find(#[case] expr: &str, #[case] line: &[u8], #[case] expect: Option<Range<usize>>) {
+        let re = Regex::new(expr).unwrap();
+        assert_eq!(re.find(line), expect);
+        assert_eq!(re.is_match(line), expect.is_some());
+    }
+
+    #[rstest]
+    #[case("é", "aé".as_bytes(), Some(1..3))]
+    #[case("é", b"a\xE9", None)]
+    #[case("(?i)é", b"\xC9", None)]
+    #[case("(?i)é", "É".as_bytes(), Some(0..2))]
+    #[case(r"\p{L}+", b"\xE9\xAA", None)]
+    #[case(r"\p{L}+", b"\xFFd\xC3\xA9j\xC3", Some(1..5))]
+    #[case(".", b"\xC3\xA9", Some(0..2))]
+    #[case("a.b", b"a\xC3b", Some(0..3))]
+    #[case("[^a]+", b"\xFF\xFEa", Some(0..2))]
+    #[case(r"\xFF", b"a\xFF", None)]
+    #[case(r"\xFF", b"a\xC3\xBF", Some(1..3))]
+    fn non_ascii(#[case] expr: &str, #[case] line: &[u8], #[case] expect: Option<Range<usize>>) {
+        assert_eq!(Regex::new(expr).unwrap().find(line), expect);
+    }
+
+    #[test]
+    fn find_iter() {
+        let re = Regex::new("a*").unwrap();
+        assert_eq!(
+            re.find_iter(b"\xFFaa\xFF").collect::<Vec<_>>(),
+            vec![0..0, 1..3, 4..4]
+        );
+    }
+
+    #[test]
+    fn from_builder() {
+        let re: Regex = RegexBuilder::new("ab|abc")
+            .match_kind(MatchKind::LeftmostLongest)
+            .build()
+            .unwrap()
+            .into();
+        assert_eq!(re.as_str(), "ab|abc");
+        assert_eq!(re.find(b"\x00abc"), Some(1..4));
+    }
+}
@@ -1,15 +1,25 @@
+use crate::engine::unicode_tables::case_folding_simple::CASE_FOLDING_SIMPLE;
+use crate::engine::unicode_tables::property_names::PROPERTY_NAMES;
+use crate::engine::unicode_tables::property_values::PROPERTY_VALUES;
+use crate::engine::unicode_tables::{general_category, property_bool, script};
+use std::cmp::Ordering;
 use std::fmt::{Display, Formatter};
 
 pub(crate) mod codegen;
 pub(crate) mod evaluator;
+pub(crate) mod input;
 pub(crate) mod parser;
+pub(crate) mod unicode_tables;
 
 #[derive(Debug, PartialEq)]
-pub struct Code(Vec<Instruction>);
+pub struct Code {
+    instructions: Vec<Instruction>,
+    capture_names: Vec<Option<String>>,
+}
 
 impl Display for Code {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        for (i, inst) in self.0.iter().enumerate() {
+        for (i, inst) in self.instructions.iter().enumerate() {
             writeln!(f, "{}: {}", i, inst)?;
         }
         Ok(())
@@ -18,7 +28,303 @@ impl Display for Code {
 
 impl Code {
     pub fn instractions(&self) -> &Vec<Instruction> {
-        &self.0
+        &self.instructions
+    }
+
+    /// Number of capture groups, including the implicit group 0 for the whole match.
+    pub fn captures_len(&self) -> usize {
+        self.capture_names.len()
+    }
+
+    /// The name of every capture group by index, `None` for group 0 and unnamed groups.
+    pub fn capture_names(&self) -> &[Option<String>] {
+        &self.capture_names
+    }
+
+    /// Whether the program uses backreferences or lookaround, which only the backtracker
+    /// supports.
+    pub fn requires_backtracking(&self) -> bool {
+        has_backrefs(&self.instructions) || has_lookaround(&self.instructions)
+    }
+}
+
+/// Whether the program refers back to a capture group.
+pub fn has_backrefs(inst: &[Instruction]) -> bool {
+    inst.iter().any(|i| matches!(i, Instruction::Backref(..)))
+}
+
+pub fn has_lookaround(inst: &[Instruction]) -> bool {
+    inst.iter().any(|i| matches!(i, Instruction::LookAround(_)))
+}
+
+pub const DIGIT_RANGES: &[(char, char)] = &[('0', '9')];
+pub const WORD_RANGES: &[(char, char)] = &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+pub const SPACE_RANGES: &[(char, char)] = &[('\t', '\r'), (' ', ' ')];
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Returns the chars not covered by `ranges`, which must be sorted and disjoint.
+pub fn negate_ranges(ranges: &[(char, char)]) -> Vec<(char, char)> {
+    let mut negated = vec![];
+    let mut next = Some('\0');
+
+    for (start, end) in ranges {
+        if let Some(from) = next {
+            if from < *start {
+                negated.push((from, prev_char(*start)));
+            }
+        }
+        next = next_char(*end);
+    }
+    if let Some(from) = next {
+        negated.push((from, char::MAX));
+    }
+
+    negated
+}
+
+/// Returns the chars that are equal to `c` under Unicode simple case folding, besides `c`.
+pub fn case_variants(c: char) -> &'static [char] {
+    match CASE_FOLDING_SIMPLE.binary_search_by_key(&c, |(c, _)| *c) {
+        Ok(i) => CASE_FOLDING_SIMPLE[i].1,
+        Err(_) => &[],
+    }
+}
+
+/// Returns `ranges` together with every case variant of the chars they cover.
+pub fn case_fold_ranges(ranges: &[(char, char)]) -> Vec<(char, char)> {
+    let mut folded = ranges.to_vec();
+
+    for (start, end) in ranges {
+        let from = CASE_FOLDING_SIMPLE.partition_point(|(c, _)| c < start);
+        for (c, variants) in CASE_FOLDING_SIMPLE[from..].iter() {
+            if c > end {
+                break;
+            }
+            folded.extend(variants.iter().map(|v| (*v, *v)));
+        }
+    }
+
+    folded
+}
+
+/// Returns the chars of a Unicode property such as `L`, `Greek`, `Alphabetic`, `gc=Lu` or
+/// `Script=Latin`, as `\p{..}` names it. Names are compared ignoring case, spaces, `_` and `-`.
+pub fn unicode_property(name: &str) -> Option<Vec<(char, char)>> {
+    let (key, value) = match name.split_once(['=', ':']) {
+        Some((key, value)) => (normalize_name(key), normalize_name(value)),
+        None => {
+            let name = normalize_name(name);
+            // `cf` is also the short name of `Case_Folding`, but means the `Format` category.
+            if name != "cf" {
+                if let Some(canon) = lookup(PROPERTY_NAMES, &name) {
+                    if let Some(ranges) = lookup(property_bool::BY_NAME, canon) {
+                        return Some(ranges.to_vec());
+                    }
+                }
+            }
+            return general_category_ranges(&name).or_else(|| script_ranges(&name));
+        }
+    };
+
+    match lookup(PROPERTY_NAMES, &key)? {
+        "General_Category" => general_category_ranges(&value),
+        "Script" => script_ranges(&value),
+        _ => None,
+    }
+}
+
+fn general_category_ranges(name: &str) -> Option<Vec<(char, char)>> {
+    match name {
+        "any" => Some(vec![('\0', char::MAX)]),
+        "ascii" => Some(vec![('\0', '\x7F')]),
+        "assigned" => Some(negate_ranges(general_category::UNASSIGNED)),
+        _ => {
+            let canon = lookup(lookup(PROPERTY_VALUES, "General_Category")?, name)?;
+            lookup(general_category::BY_NAME, canon).map(|r| r.to_vec())
+        }
+    }
+}
+
+fn script_ranges(name: &str) -> Option<Vec<(char, char)>> {
+    let canon = lookup(lookup(PROPERTY_VALUES, "Script")?, name)?;
+    lookup(script::BY_NAME, canon).map(|r| r.to_vec())
+}
+
+/// Finds `key` in one of the sorted tables of `unicode_tables`.
+fn lookup<T: Copy>(table: &[(&str, T)], key: &str) -> Option<T> {
+    table
+        .binary_search_by_key(&key, |(k, _)| k)
+        .ok()
+        .map(|i| table[i].1)
+}
+
+fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, ' ' | '_' | '-'))
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn prev_char(c: char) -> char {
+    match c {
+        '\u{E000}' => '\u{D7FF}',
+        _ => char::from_u32(c as u32 - 1).unwrap(),
+    }
+}
+
+fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        char::MAX => None,
+        _ => char::from_u32(c as u32 + 1),
+    }
+}
+
+/// A set of chars, kept as sorted, disjoint and non-adjacent inclusive ranges so that equal
+/// sets compare equal. Bracket expressions and classes such as `\d` are normalized to it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CharClass {
+    ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    pub fn new(mut ranges: Vec<(char, char)>, negated: bool) -> Self {
+        ranges.sort_unstable();
+
+        let mut merged: Vec<(char, char)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if next_char(last.1).is_none_or(|next| start <= next) => {
+                    last.1 = last.1.max(end);
+                }
+                _ => merged.push((start, end)),
+            }
+        }
+
+        let class = Self { ranges: merged };
+        if negated {
+            class.negate()
+        } else {
+            class
+        }
+    }
+
+    pub fn ranges(&self) -> &[(char, char)] {
+        &self.ranges
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self::new([&self.ranges[..], &other.ranges[..]].concat(), false)
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        let mut ranges = vec![];
+        let (mut a, mut b) = (
+            self.ranges.iter().peekable(),
+            other.ranges.iter().peekable(),
+        );
+
+        while let (Some(x), Some(y)) = (a.peek(), b.peek()) {
+            let (start, end) = (x.0.max(y.0), x.1.min(y.1));
+            if start <= end {
+                ranges.push((start, end));
+            }
+            if x.1 < y.1 {
+                a.next();
+            } else {
+                b.next();
+            }
+        }
+
+        Self { ranges }
+    }
+
+    pub fn difference(&self, other: &Self) -> Self {
+        self.intersection(&other.negate())
+    }
+
+    pub fn negate(&self) -> Self {
+        Self {
+            ranges: negate_ranges(&self.ranges),
+        }
+    }
+
+    /// Returns a class that also matches every case variant of the chars this one covers.
+    pub fn case_fold(&self) -> Self {
+        Self::new(case_fold_ranges(&self.ranges), false)
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        ranges_contain(&self.ranges, c)
+    }
+}
+
+/// Whether `c` is in `ranges`, which are sorted and do not overlap.
+pub fn ranges_contain(ranges: &[(char, char)], c: char) -> bool {
+    ranges
+        .binary_search_by(|(start, end)| {
+            if *end < c {
+                Ordering::Less
+            } else if *start > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+impl Display for CharClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // A set holding both ends of the char range reads better as the negation of its gaps.
+        let negated = matches!(
+            (self.ranges.first(), self.ranges.last()),
+            (Some(('\0', _)), Some((_, char::MAX)))
+        );
+        let ranges = if negated {
+            negate_ranges(&self.ranges)
+        } else {
+            self.ranges.clone()
+        };
+
+        write!(f, "[")?;
+        if negated {
+            write!(f, "^")?;
+        }
+        for (start, end) in ranges.iter() {
+            if start == end {
+                write!(f, "{}", start.escape_debug())?;
+            } else {
+                write!(f, "{}-{}", start.escape_debug(), end.escape_debug())?;
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+/// A lookaround assertion, whose body runs from the next pc up to a `LookMatch`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LookAround {
+    /// Whether the body has to match, rather than not match.
+    pub positive: bool,
+    /// For a lookbehind, the least and the most chars the body can match, so that it is tried
+    /// from every start in that range and has to end where the assertion is.
+    pub behind: Option<(usize, usize)>,
+    /// The pc after the `LookMatch`, where matching resumes.
+    pub end: usize,
+}
+
+impl Display for LookAround {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.positive { "=" } else { "!" };
+        match self.behind {
+            Some((min, max)) => write!(f, "<{} {}..={}", sign, min, max)?,
+            None => write!(f, "{}", sign)?,
+        }
+        write!(f, ", {:>04}", self.end)
     }
 }
 
@@ -26,9 +332,25 @@ impl Code {
 pub enum Instruction {
     Char(char),
     AnyChar,
+    AnyCharExceptNewline,
+    Class(CharClass),
     Start,
+    Save(usize),
     IsHead,
     IsTail,
+    IsWordBoundary,
+    IsNotWordBoundary,
+    /// Holds at the start of the input or after a line break. With the flag set, a line may
+    /// also end with `\r`, but never between `\r` and `\n`.
+    IsLineStart(bool),
+    /// Holds at the end of the input or before a line break, with the same flag.
+    IsLineEnd(bool),
+    /// Matches the text last captured by the group, and fails if the group is unset. With the
+    /// flag set, chars also match their case variants.
+    Backref(usize, bool),
+    LookAround(LookAround),
+    /// Ends the body of a lookaround.
+    LookMatch,
     Match,
     Jump(usize),
     Split(usize, usize),
@@ -40,9 +362,20 @@ impl Display for Instruction {
         match self {
             Instruction::Char(c) => write!(f, "Char {} ", c),
             Instruction::AnyChar => write!(f, "Anychar"),
+            Instruction::AnyCharExceptNewline => write!(f, "Anycharexceptnewline"),
+            Instruction::Class(class) => write!(f, "Class {}", class),
             Instruction::Start => write!(f, "Start"),
+            Instruction::Save(slot) => write!(f, "Save {}", slot),
             Instruction::IsHead => write!(f, "Ishead"),
             Instruction::IsTail => write!(f, "Istail"),
+            Instruction::IsWordBoundary => write!(f, "Iswordboundary"),
+            Instruction::IsNotWordBoundary => write!(f, "Isnotwordboundary"),
+            Instruction::IsLineStart(crlf) => write!(f, "Islinestart {}", crlf),
+            Instruction::IsLineEnd(crlf) => write!(f, "Islineend {}", crlf),
+            Instruction::Backref(group, false) => write!(f, "Backref {}", group),
+            Instruction::Backref(group, true) => write!(f, "Backref {} (?i)", group),
+            Instruction::LookAround(look) => write!(f, "Lookaround {}", look),
+            Instruction::LookMatch => write!(f, "Lookmatch"),
             Instruction::Match => write!(f, "Match"),
             Instruction::Jump(i) => write!(f, "Jump {:>04}", i),
             Instruction::Split(i, j) => write!(f, "Split {:>04}, {:>04}", i, j),
@@ -50,3 +383,49 @@ impl Display for Instruction {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn char_class_set_ops() {
+        let a = CharClass::new(vec![('m', 'z'), ('a', 'f'), ('e', 'h'), ('i', 'i')], false);
+        assert_eq!(a, CharClass::new(vec![('a', 'i'), ('m', 'z')], false));
+        assert!(a.contains('i') && !a.contains('j') && a.contains('z'));
+
+        let b = CharClass::new(vec![('c', 'n')], false);
+        assert_eq!(a.union(&b), CharClass::new(vec![('a', 'z')], false));
+        assert_eq!(
+            a.intersection(&b),
+            CharClass::new(vec![('c', 'i'), ('m', 'n')], false)
+        );
+        assert_eq!(
+            a.difference(&b),
+            CharClass::new(vec![('a', 'b'), ('o', 'z')], false)
+        );
+        assert_eq!(a.negate().negate(), a);
+        assert!(!a.negate().contains('a') && a.negate().contains(char::MAX));
+
+        let around_surrogates = CharClass::new(
+            vec![('\u{E000}', '\u{E000}'), ('\u{D7FF}', '\u{D7FF}')],
+            false,
+        );
+        assert_eq!(
+            around_surrogates,
+            CharClass::new(vec![('\u{D7FF}', '\u{E000}')], false)
+        );
+    }
+
+    #[test]
+    fn char_class_display() {
+        assert_eq!(
+            CharClass::new(vec![('a', 'c'), ('x', 'x')], false).to_string(),
+            "[a-cx]"
+        );
+        assert_eq!(
+            CharClass::new(vec![('\n', '\n')], true).to_string(),
+            "[^\\n]"
+        );
+    }
+}
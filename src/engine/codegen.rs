@@ -1,13 +1,23 @@
 use crate::engine::parser::AST;
-use crate::engine::{Code, Instruction};
+use crate::engine::{CharClass, Code, Instruction, LookAround};
 use std::fmt::Display;
 
+/// Default upper bound on the number of instructions in a program.
+pub const DEFAULT_SIZE_LIMIT: usize = 100_000;
+
 pub fn get_code(ast: &AST) -> Result<Code, CodeGenError> {
-    let mut generator = Generator::new();
+    get_code_with_limit(ast, DEFAULT_SIZE_LIMIT)
+}
+
+pub fn get_code_with_limit(ast: &AST, size_limit: usize) -> Result<Code, CodeGenError> {
+    let mut generator = Generator::new(size_limit);
 
     generator.gen_code(ast)?;
 
-    Ok(Code(generator.instructions))
+    Ok(Code {
+        instructions: generator.instructions,
+        capture_names: ast.capture_names(),
+    })
 }
 
 #[derive(Debug)]
@@ -17,6 +27,8 @@ pub enum CodeGenError {
     FailStar,
     FailOr,
     FailQuestion,
+    FailLookbehind,
+    SizeLimitExceeded(usize),
 }
 
 impl Display for CodeGenError {
@@ -26,27 +38,54 @@ impl Display for CodeGenError {
             CodeGenError::FailStar => write!(f, "fail star"),
             CodeGenError::FailOr => write!(f, "fail or"),
             CodeGenError::FailQuestion => write!(f, "fail question"),
+            CodeGenError::FailLookbehind => write!(f, "fail lookbehind"),
+            CodeGenError::SizeLimitExceeded(limit) => {
+                write!(f, "compiled program exceeds the size limit of {}", limit)
+            }
         }
     }
 }
 
 impl std::error::Error for CodeGenError {}
 
+/// A `Split` that prefers `body` when greedy and `exit` when lazy.
+fn split(body: usize, exit: usize, greedy: bool) -> Instruction {
+    if greedy {
+        Instruction::Split(body, exit)
+    } else {
+        Instruction::Split(exit, body)
+    }
+}
+
 struct Generator {
     pc: usize,
     instructions: Vec<Instruction>,
+    /// The instructions so far plus the ranges of their classes, which `size_limit` bounds.
+    size: usize,
+    size_limit: usize,
 }
 
 impl Generator {
-    fn new() -> Self {
+    fn new(size_limit: usize) -> Self {
         Self {
             pc: 0,
             instructions: vec![],
+            size: 0,
+            size_limit,
         }
     }
 
     fn inc_pc(&mut self) -> Result<(), CodeGenError> {
         self.pc = self.pc.checked_add(1).ok_or(CodeGenError::PcOverFlow)?;
+        self.charge(1)
+    }
+
+    /// Counts `n` more units against the size limit.
+    fn charge(&mut self, n: usize) -> Result<(), CodeGenError> {
+        self.size = self.size.saturating_add(n);
+        if self.size > self.size_limit {
+            return Err(CodeGenError::SizeLimitExceeded(self.size_limit));
+        }
 
         Ok(())
     }
@@ -54,14 +93,34 @@ impl Generator {
     fn gen_expr(&mut self, ast: &AST) -> Result<(), CodeGenError> {
         match ast {
             AST::Char(c) => self.gen_char(*c)?,
-            AST::AnyChar => self.gen_anychar()?,
-            AST::Dollar(ast) => self.gen_dollar(ast)?,
-            AST::Hat(ast) => self.gen_hat(ast)?,
+            AST::AnyChar => self.gen_anychar(Instruction::AnyChar)?,
+            AST::AnyCharExceptNewline => self.gen_anychar(Instruction::AnyCharExceptNewline)?,
+            AST::Class(class) => self.gen_class(class)?,
+            AST::WordBoundary => self.gen_assertion(Instruction::IsWordBoundary)?,
+            AST::NotWordBoundary => self.gen_assertion(Instruction::IsNotWordBoundary)?,
+            AST::LineStart(crlf) => self.gen_assertion(Instruction::IsLineStart(*crlf))?,
+            AST::LineEnd(crlf) => self.gen_assertion(Instruction::IsLineEnd(*crlf))?,
+            AST::Hat => self.gen_assertion(Instruction::IsHead)?,
+            AST::Dollar => self.gen_assertion(Instruction::IsTail)?,
             AST::Or(ast1, ast2) => self.gen_or(ast1, ast2)?,
-            AST::Plus(ast) => self.gen_plus(ast)?,
-            AST::Star(ast) => self.gen_star(ast)?,
-            AST::Question(ast) => self.gen_question(ast)?,
+            AST::Plus(ast, greedy) => self.gen_plus(ast, *greedy)?,
+            AST::Star(ast, greedy) => self.gen_star(ast, *greedy)?,
+            AST::Question(ast, greedy) => self.gen_question(ast, *greedy)?,
+            AST::Repeat {
+                ast,
+                min,
+                max,
+                greedy,
+            } => self.gen_repeat(ast, *min, *max, *greedy)?,
             AST::Seq(asts) => self.gen_seq(asts)?,
+            AST::Capture(index, _, ast) => self.gen_capture(*index, ast)?,
+            AST::Backref(group, case_insensitive) => self.gen_backref(*group, *case_insensitive)?,
+            AST::Lookahead(ast, positive) => self.gen_look(ast, None, *positive)?,
+            AST::Lookbehind(ast, positive) => {
+                let (min, max) = ast.len_bounds();
+                let max = max.ok_or(CodeGenError::FailLookbehind)?;
+                self.gen_look(ast, Some((min, max)), *positive)?
+            }
         }
 
         Ok(())
@@ -73,22 +132,22 @@ impl Generator {
         Ok(())
     }
 
-    fn gen_anychar(&mut self) -> Result<(), CodeGenError> {
-        self.instructions.push(Instruction::AnyChar);
+    fn gen_anychar(&mut self, inst: Instruction) -> Result<(), CodeGenError> {
+        self.instructions.push(inst);
         self.inc_pc()?;
         Ok(())
     }
 
-    fn gen_hat(&mut self, expr: &AST) -> Result<(), CodeGenError> {
-        self.instructions.push(Instruction::IsHead);
+    fn gen_class(&mut self, class: &CharClass) -> Result<(), CodeGenError> {
+        // Every copy of a class owns its ranges, so they count towards the size as well.
+        self.charge(class.ranges().len())?;
+        self.instructions.push(Instruction::Class(class.clone()));
         self.inc_pc()?;
-        self.gen_expr(expr)?;
         Ok(())
     }
 
-    fn gen_dollar(&mut self, expr: &AST) -> Result<(), CodeGenError> {
-        self.gen_expr(expr)?;
-        self.instructions.push(Instruction::IsTail);
+    fn gen_assertion(&mut self, inst: Instruction) -> Result<(), CodeGenError> {
+        self.instructions.push(inst);
         self.inc_pc()?;
         Ok(())
     }
@@ -111,7 +170,7 @@ impl Generator {
         Ok(())
     }
 
-    fn gen_question(&mut self, expr: &AST) -> Result<(), CodeGenError> {
+    fn gen_question(&mut self, expr: &AST, greedy: bool) -> Result<(), CodeGenError> {
         self.instructions.push(Instruction::Nop);
         let split_inst_pc = self.pc;
         self.inc_pc()?;
@@ -119,12 +178,12 @@ impl Generator {
 
         self.gen_expr(expr)?;
 
-        self.instructions[split_inst_pc] = Instruction::Split(split_branch1_pc, self.pc);
+        self.instructions[split_inst_pc] = split(split_branch1_pc, self.pc, greedy);
 
         Ok(())
     }
 
-    fn gen_plus(&mut self, expr: &AST) -> Result<(), CodeGenError> {
+    fn gen_plus(&mut self, expr: &AST, greedy: bool) -> Result<(), CodeGenError> {
         let jump_pc = self.pc;
 
         self.gen_expr(expr)?;
@@ -132,12 +191,12 @@ impl Generator {
         self.instructions.push(Instruction::Nop);
         self.inc_pc()?;
 
-        self.instructions[split_pc] = Instruction::Split(jump_pc, self.pc);
+        self.instructions[split_pc] = split(jump_pc, self.pc, greedy);
 
         Ok(())
     }
 
-    fn gen_star(&mut self, expr: &AST) -> Result<(), CodeGenError> {
+    fn gen_star(&mut self, expr: &AST, greedy: bool) -> Result<(), CodeGenError> {
         let split_pc = self.pc;
         self.instructions.push(Instruction::Nop);
         self.inc_pc()?;
@@ -147,7 +206,86 @@ impl Generator {
         self.instructions.push(Instruction::Jump(split_pc));
         self.inc_pc()?;
 
-        self.instructions[split_pc] = Instruction::Split(branch1_pc, self.pc);
+        self.instructions[split_pc] = split(branch1_pc, self.pc, greedy);
+
+        Ok(())
+    }
+
+    /// Unrolls `min` copies of the body, followed by either a star or `max - min` optional
+    /// copies that all exit to the same pc.
+    fn gen_repeat(
+        &mut self,
+        expr: &AST,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    ) -> Result<(), CodeGenError> {
+        for _ in 0..min {
+            let start = self.pc;
+            self.gen_expr(expr)?;
+            // A body that emits nothing, like `(?:)`, would emit nothing for every other copy too.
+            if self.pc == start {
+                break;
+            }
+        }
+
+        if let Some(max) = max {
+            let mut split_pcs = vec![];
+            for _ in min..max {
+                split_pcs.push(self.pc);
+                self.instructions.push(Instruction::Nop);
+                self.inc_pc()?;
+                self.gen_expr(expr)?;
+            }
+
+            for split_pc in split_pcs {
+                self.instructions[split_pc] = split(split_pc + 1, self.pc, greedy);
+            }
+        } else {
+            self.gen_star(expr, greedy)?;
+        }
+
+        Ok(())
+    }
+
+    fn gen_capture(&mut self, index: usize, expr: &AST) -> Result<(), CodeGenError> {
+        self.instructions.push(Instruction::Save(index * 2));
+        self.inc_pc()?;
+        self.gen_expr(expr)?;
+        self.instructions.push(Instruction::Save(index * 2 + 1));
+        self.inc_pc()?;
+
+        Ok(())
+    }
+
+    fn gen_backref(&mut self, group: usize, case_insensitive: bool) -> Result<(), CodeGenError> {
+        self.instructions
+            .push(Instruction::Backref(group, case_insensitive));
+        self.inc_pc()?;
+
+        Ok(())
+    }
+
+    /// Emits the body of a lookaround between its `LookAround` and a `LookMatch`.
+    fn gen_look(
+        &mut self,
+        expr: &AST,
+        behind: Option<(usize, usize)>,
+        positive: bool,
+    ) -> Result<(), CodeGenError> {
+        let look_pc = self.pc;
+        self.instructions.push(Instruction::Nop);
+        self.inc_pc()?;
+
+        self.gen_expr(expr)?;
+        self.instructions.push(Instruction::LookMatch);
+        self.inc_pc()?;
+
+        self.instructions[look_pc] = Instruction::LookAround(LookAround {
+            positive,
+            behind,
+            end: self.pc,
+        });
 
         Ok(())
     }
@@ -183,3 +321,95 @@ impl Generator {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::parser::parse;
+
+    #[test]
+    fn repeat_code() {
+        let code = get_code(&parse("a{2,4}").unwrap()).unwrap();
+        assert_eq!(
+            code.instractions()[4..],
+            [
+                Instruction::Char('a'),
+                Instruction::Char('a'),
+                Instruction::Split(7, 10),
+                Instruction::Char('a'),
+                Instruction::Split(9, 10),
+                Instruction::Char('a'),
+                Instruction::Match,
+            ]
+        );
+    }
+
+    #[test]
+    fn lazy_code() {
+        let code = get_code(&parse("a*?b??").unwrap()).unwrap();
+        assert_eq!(
+            code.instractions()[4..],
+            [
+                Instruction::Split(7, 5),
+                Instruction::Char('a'),
+                Instruction::Jump(4),
+                Instruction::Split(9, 8),
+                Instruction::Char('b'),
+                Instruction::Match,
+            ]
+        );
+    }
+
+    #[test]
+    fn size_limit() {
+        let ast = parse("a{1000}{1000}").unwrap();
+        assert!(matches!(
+            get_code(&ast),
+            Err(CodeGenError::SizeLimitExceeded(DEFAULT_SIZE_LIMIT))
+        ));
+        assert!(matches!(
+            get_code_with_limit(&parse("a{10}").unwrap(), 10),
+            Err(CodeGenError::SizeLimitExceeded(10))
+        ));
+        assert!(get_code_with_limit(&parse("a{10}").unwrap(), 15).is_ok());
+
+        assert!(matches!(
+            get_code(&parse(r"\p{L}{99990}").unwrap()),
+            Err(CodeGenError::SizeLimitExceeded(DEFAULT_SIZE_LIMIT))
+        ));
+        assert!(matches!(
+            get_code_with_limit(&parse("[a-cx-z]{3}").unwrap(), 10),
+            Err(CodeGenError::SizeLimitExceeded(10))
+        ));
+        assert!(get_code_with_limit(&parse("[a-cx-z]{3}").unwrap(), 15).is_ok());
+
+        assert!(get_code(&parse("(?:){1000000000}{1000000000}").unwrap()).is_ok());
+        assert!(matches!(
+            get_code(&parse("(?:){0,1000000000}{1000000000}").unwrap()),
+            Err(CodeGenError::SizeLimitExceeded(DEFAULT_SIZE_LIMIT))
+        ));
+    }
+
+    #[test]
+    fn look_code() {
+        let code = get_code(&parse("(?<!ab|c)d").unwrap()).unwrap();
+        assert_eq!(
+            code.instractions()[4..],
+            [
+                Instruction::LookAround(LookAround {
+                    positive: false,
+                    behind: Some((1, 2)),
+                    end: 11,
+                }),
+                Instruction::Split(6, 9),
+                Instruction::Char('a'),
+                Instruction::Char('b'),
+                Instruction::Jump(10),
+                Instruction::Char('c'),
+                Instruction::LookMatch,
+                Instruction::Char('d'),
+                Instruction::Match,
+            ]
+        );
+    }
+}
@@ -1,25 +1,74 @@
-use crate::engine::{Code, Instruction};
-use std::collections::VecDeque;
+use crate::engine::input::Input;
+use crate::engine::{
+    case_variants, has_backrefs, has_lookaround, is_word_char, Code, Instruction, LookAround,
+};
+use std::collections::HashSet;
 use std::fmt::Display;
 use std::ops::Range;
 
-pub fn eval(code: &Code, line: &[char], is_depth: bool) -> Result<bool, EvalError> {
+/// Which match to report when several start at the leftmost position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchKind {
+    /// The first match in priority order, as Perl and most backtracking engines report.
+    #[default]
+    LeftmostFirst,
+    /// The longest match, as POSIX requires.
+    LeftmostLongest,
+}
+
+pub fn eval<I: Input + ?Sized>(code: &Code, line: &I, is_depth: bool) -> Result<bool, EvalError> {
+    let slots = vec![None; code.captures_len() * 2];
+
     if is_depth {
-        eval_depth(code.instractions(), line, 0, 0)
+        let kind = MatchKind::LeftmostFirst;
+        Ok(eval_depth(code.instractions(), line, 0, slots, kind)?.is_some())
     } else {
-        eval_width(code.instractions(), line)
+        Ok(eval_width(code.instractions(), line, slots)?.is_some())
     }
 }
 
-pub fn eval_with_pattern(
+pub fn eval_shortest<I: Input + ?Sized>(code: &Code, line: &I) -> Result<Option<usize>, EvalError> {
+    let slots = vec![None; code.captures_len() * 2];
+    eval_width(code.instractions(), line, slots)
+}
+
+pub fn eval_with_pattern<I: Input + ?Sized>(
     code: &Code,
-    line: &[char],
+    line: &I,
+    start: usize,
     is_depth: bool,
+    kind: MatchKind,
 ) -> Result<Option<Range<usize>>, EvalError> {
+    let slots = eval_with_captures(code, line, start, is_depth, kind)?;
+    Ok(slots.and_then(|slots| group_range(&slots, 0)))
+}
+
+/// Returns the capture slots of the first match that starts at or after `start`, where group
+/// `i` spans `slots[2 * i]..slots[2 * i + 1]`. Positions are byte offsets into `line`, and
+/// `start` must be on a char boundary.
+pub fn eval_with_captures<I: Input + ?Sized>(
+    code: &Code,
+    line: &I,
+    start: usize,
+    is_depth: bool,
+    kind: MatchKind,
+) -> Result<Option<Vec<Option<usize>>>, EvalError> {
+    if start > line.len() {
+        return Ok(None);
+    }
+    let slots = vec![None; code.captures_len() * 2];
+
     if is_depth {
-        eval_depth_with_pattern(code.instractions(), line, 0, 0, None)
+        eval_depth(code.instractions(), line, start, slots, kind)
     } else {
-        eval_width_with_pattern(code.instractions(), line)
+        eval_pike_vm(code.instractions(), line, start, slots, kind, false)
+    }
+}
+
+pub fn group_range(slots: &[Option<usize>], i: usize) -> Option<Range<usize>> {
+    match (slots.get(i * 2)?, slots.get(i * 2 + 1)?) {
+        (Some(start), Some(end)) => Some(*start..*end),
+        _ => None,
     }
 }
 
@@ -30,6 +79,9 @@ pub enum EvalError {
     SPOverFlow,
     InvalidPC,
     InvalidContext,
+    InvalidSlot,
+    UnsupportedBackref,
+    UnsupportedLookAround,
     AttemptNop,
     NotStarted,
 }
@@ -41,6 +93,16 @@ impl Display for EvalError {
             EvalError::SPOverFlow => write!(f, "sp overflow"),
             EvalError::InvalidPC => write!(f, "invalid pc"),
             EvalError::InvalidContext => write!(f, "invalid context"),
+            EvalError::InvalidSlot => write!(f, "invalid slot"),
+            EvalError::UnsupportedBackref => {
+                write!(
+                    f,
+                    "backreferences are only supported by the depth-first engine"
+                )
+            }
+            EvalError::UnsupportedLookAround => {
+                write!(f, "lookaround is only supported by the depth-first engine")
+            }
             EvalError::AttemptNop => write!(f, "attempt nop"),
             EvalError::NotStarted => write!(f, "not started"),
         }
@@ -49,236 +111,650 @@ impl Display for EvalError {
 
 impl std::error::Error for EvalError {}
 
-fn eval_width_with_pattern(
+/// Returns the length in bytes of the char at `sp` if a char-consuming instruction accepts it.
+fn consumes<I: Input + ?Sized>(inst: &Instruction, line: &I, sp: usize) -> Option<usize> {
+    let (c, len) = line.char_at(sp)?;
+
+    let accepted = match inst {
+        Instruction::Char(expected) => *expected == c,
+        Instruction::AnyChar => true,
+        Instruction::AnyCharExceptNewline => c != '\n',
+        Instruction::Class(class) => class.contains(c),
+        _ => false,
+    };
+    accepted.then_some(len)
+}
+
+/// Whether a zero-width assertion holds at `sp`.
+fn asserts<I: Input + ?Sized>(inst: &Instruction, line: &I, sp: usize) -> bool {
+    match inst {
+        Instruction::IsHead => sp == 0,
+        Instruction::IsTail => sp == line.len(),
+        Instruction::IsWordBoundary => is_word_boundary(line, sp),
+        Instruction::IsNotWordBoundary => !is_word_boundary(line, sp),
+        Instruction::IsLineStart(crlf) => is_line_start(line, sp, *crlf),
+        Instruction::IsLineEnd(crlf) => is_line_end(line, sp, *crlf),
+        _ => false,
+    }
+}
+
+fn is_line_start<I: Input + ?Sized>(line: &I, sp: usize, crlf: bool) -> bool {
+    match line.char_before(sp) {
+        None | Some(('\n', _)) => true,
+        Some(('\r', _)) if crlf => line.char_at(sp).is_none_or(|(c, _)| c != '\n'),
+        _ => false,
+    }
+}
+
+fn is_line_end<I: Input + ?Sized>(line: &I, sp: usize, crlf: bool) -> bool {
+    match line.char_at(sp) {
+        None => true,
+        Some(('\n', _)) => !crlf || line.char_before(sp).is_none_or(|(c, _)| c != '\r'),
+        Some(('\r', _)) => crlf,
+        _ => false,
+    }
+}
+
+fn is_word_boundary<I: Input + ?Sized>(line: &I, sp: usize) -> bool {
+    let before = line.char_before(sp).is_some_and(|(c, _)| is_word_char(c));
+    let after = line.char_at(sp).is_some_and(|(c, _)| is_word_char(c));
+
+    before != after
+}
+
+/// Returns the length in bytes of the text at `sp` that repeats what `group` captured, where
+/// with `case_insensitive` each char may also be one of its case variants.
+fn backref_len<I: Input + ?Sized>(
+    line: &I,
+    slots: &[Option<usize>],
+    group: usize,
+    case_insensitive: bool,
+    sp: usize,
+) -> Option<usize> {
+    let captured = group_range(slots, group)?;
+    let (mut at, mut end) = (captured.start, sp);
+
+    while at < captured.end {
+        let (expected, len) = line.char_at(at)?;
+        let (c, c_len) = line.char_at(end)?;
+        if c != expected && !(case_insensitive && case_variants(expected).contains(&c)) {
+            return None;
+        }
+        at += len;
+        end += c_len;
+    }
+
+    Some(end - sp)
+}
+
+fn save(slots: &mut [Option<usize>], slot: usize, sp: usize) -> Result<(), EvalError> {
+    *slots.get_mut(slot).ok_or(EvalError::InvalidSlot)? = Some(sp);
+    Ok(())
+}
+
+/// Ends group 0 at `sp` and returns the finished slots.
+fn finish(mut slots: Vec<Option<usize>>, sp: usize) -> Result<Vec<Option<usize>>, EvalError> {
+    if slots.first().ok_or(EvalError::InvalidSlot)?.is_none() {
+        return Err(EvalError::NotStarted);
+    }
+    save(&mut slots, 1, sp)?;
+
+    Ok(slots)
+}
+
+/// Whether `candidate` starts further left than `current`, or at the same place and ends later.
+fn is_leftmost_longer(current: &Option<Vec<Option<usize>>>, candidate: &[Option<usize>]) -> bool {
+    let Some(current) = current else {
+        return true;
+    };
+
+    match (group_range(current, 0), group_range(candidate, 0)) {
+        (Some(a), Some(b)) => b.start < a.start || (b.start == a.start && b.end > a.end),
+        _ => false,
+    }
+}
+
+/// The (pc, sp) states the backtracker has already explored, as a bitset.
+/// Only positions from `start` onwards are tracked, since the search never goes back, and the
+/// bitset only grows as far as the search gets, so a search that stops early stays cheap.
+struct Visited {
+    bits: Vec<u64>,
+    start: usize,
+    inst_len: usize,
+}
+
+impl Visited {
+    fn new(inst_len: usize, start: usize) -> Self {
+        Self {
+            bits: vec![],
+            start,
+            inst_len,
+        }
+    }
+
+    fn insert(&mut self, pc: usize, sp: usize) -> bool {
+        let i = (sp - self.start) * self.inst_len + pc;
+        let (word, bit) = (i / 64, 1 << (i % 64));
+
+        if word >= self.bits.len() {
+            let len = (word + 1).max(self.bits.len() * 2);
+            self.bits.resize(len, 0);
+        }
+        if self.bits[word] & bit != 0 {
+            return false;
+        }
+        self.bits[word] |= bit;
+        true
+    }
+}
+
+/// The states the backtracker has already explored. Once a backreference makes the ends of a
+/// path depend on its captures, a state also includes the captures.
+enum Seen {
+    Positions(Visited),
+    States(HashSet<(usize, usize, Vec<Option<usize>>)>),
+}
+
+impl Seen {
+    fn insert(&mut self, pc: usize, sp: usize, slots: &[Option<usize>]) -> bool {
+        match self {
+            Seen::Positions(visited) => visited.insert(pc, sp),
+            Seen::States(states) => states.insert((pc, sp, slots.to_vec())),
+        }
+    }
+}
+
+/// What a backtracking search is looking for.
+#[derive(Clone, Copy)]
+enum Goal {
+    /// A match of the whole program.
+    Match(MatchKind),
+    /// The end of a lookaround body, which has to be at the given position for a lookbehind.
+    Look(Option<usize>),
+}
+
+enum Backtrack {
+    Explore(usize, usize),
+    RestoreSlot(usize, Option<usize>),
+}
+
+/// Saves `sp` into `slots[slot]` and leaves a job that restores it when backtracking past it.
+fn save_with_restore(
+    stack: &mut Vec<Backtrack>,
+    slots: &mut [Option<usize>],
+    slot: usize,
+    sp: usize,
+) -> Result<(), EvalError> {
+    let prev = *slots.get(slot).ok_or(EvalError::InvalidSlot)?;
+    stack.push(Backtrack::RestoreSlot(slot, prev));
+    save(slots, slot, sp)
+}
+
+/// Runs the program as a backtracking search that tries the first branch of a `Split` before
+/// the second, so the first match found is the leftmost-first one. For leftmost-longest it
+/// keeps searching and remembers the best match instead.
+/// Every path through a (pc, sp) state leads to the same ends, and the first path to reach it
+/// has the leftmost start, so each state is explored at most once.
+fn eval_depth<I: Input + ?Sized>(
     inst: &[Instruction],
-    line: &[char],
-) -> Result<Option<Range<usize>>, EvalError> {
-    todo!()
+    line: &I,
+    start: usize,
+    slots: Vec<Option<usize>>,
+    kind: MatchKind,
+) -> Result<Option<Vec<Option<usize>>>, EvalError> {
+    backtrack(inst, line, 0, start, slots, Goal::Match(kind))
 }
 
-fn eval_depth_with_pattern(
+/// Returns the slots to go on with if the lookaround at `pc` holds at `sp`. Its body is
+/// searched on its own, and a positive lookaround keeps the captures its body set.
+fn look_holds<I: Input + ?Sized>(
     inst: &[Instruction],
-    line: &[char],
-    mut pc: usize,
-    mut sp: usize,
-    mut ssp: Option<usize>,
-) -> Result<Option<Range<usize>>, EvalError> {
-    loop {
-        let next = inst.get(pc).ok_or(EvalError::InvalidPC)?;
+    line: &I,
+    look: &LookAround,
+    pc: usize,
+    sp: usize,
+    slots: &[Option<usize>],
+) -> Result<Option<Vec<Option<usize>>>, EvalError> {
+    let body = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
 
-        match next {
-            Instruction::Char(c) => {
-                if let Some(sp_c) = line.get(sp) {
-                    if *c == *sp_c {
-                        pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
-                        sp = sp.checked_add(1).ok_or(EvalError::SPOverFlow)?;
-                    } else {
-                        return Ok(None);
+    let found = match look.behind {
+        None => backtrack(inst, line, body, sp, slots.to_vec(), Goal::Look(None))?,
+        Some((min, max)) => {
+            let mut found = None;
+            let mut start = sp;
+            for chars in 0..=max {
+                let goal = Goal::Look(Some(sp));
+                if chars >= min {
+                    found = backtrack(inst, line, body, start, slots.to_vec(), goal)?;
+                    if found.is_some() {
+                        break;
                     }
-                } else {
-                    return Ok(None);
-                }
-            }
-            Instruction::AnyChar => {
-                if let Some(_) = line.get(sp) {
-                    pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
-                    sp = sp.checked_add(1).ok_or(EvalError::SPOverFlow)?;
-                } else {
-                    return Ok(None);
                 }
-            }
-            Instruction::Start => {
-                pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
-                ssp = Some(sp);
-            }
-            Instruction::IsHead => {
-                if sp == 0 {
-                    pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
-                } else {
-                    return Ok(None);
-                }
-            }
-            Instruction::IsTail => {
-                if sp == line.len() {
-                    pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
-                } else {
-                    return Ok(None);
+
+                match line.char_before(start) {
+                    Some((_, len)) => start -= len,
+                    None => break,
                 }
             }
-            Instruction::Match => return Ok(Some(ssp.ok_or(EvalError::NotStarted)?..sp)),
-            Instruction::Jump(i) => {
-                pc = *i;
-            }
-            Instruction::Split(branch1, branch2) => {
-                let a = eval_depth_with_pattern(inst, line, *branch1, sp, ssp)?;
-                let b = eval_depth_with_pattern(inst, line, *branch2, sp, ssp)?;
-
-                return Ok(match (a, b) {
-                    (Some(a), Some(b)) => {
-                        if a.len() >= b.len() {
-                            Some(a)
-                        } else {
-                            Some(b)
-                        }
-                    }
-                    (None, Some(b)) => Some(b),
-                    (Some(a), None) => Some(a),
-                    (None, None) => None,
-                });
-            }
-            Instruction::Nop => {
-                return Err(EvalError::AttemptNop);
-            }
+            found
         }
-    }
+    };
+
+    Ok(match (found, look.positive) {
+        (Some(found), true) => Some(found),
+        (None, false) => Some(slots.to_vec()),
+        _ => None,
+    })
 }
 
-fn eval_depth(
+/// Runs the backtracking search from `pc` at `start` until it reaches `goal`.
+fn backtrack<I: Input + ?Sized>(
     inst: &[Instruction],
-    line: &[char],
-    mut pc: usize,
-    mut sp: usize,
-) -> Result<bool, EvalError> {
-    loop {
-        // display_context(inst, line, pc, sp);
-        let next = inst.get(pc).ok_or(EvalError::InvalidPC)?;
+    line: &I,
+    pc: usize,
+    start: usize,
+    mut slots: Vec<Option<usize>>,
+    goal: Goal,
+) -> Result<Option<Vec<Option<usize>>>, EvalError> {
+    let mut seen = if has_backrefs(inst) {
+        Seen::States(HashSet::new())
+    } else {
+        Seen::Positions(Visited::new(inst.len(), start))
+    };
+    let mut stack = vec![Backtrack::Explore(pc, start)];
+    let mut matched: Option<Vec<Option<usize>>> = None;
+
+    while let Some(job) = stack.pop() {
+        let (mut pc, mut sp) = match job {
+            Backtrack::Explore(pc, sp) => (pc, sp),
+            Backtrack::RestoreSlot(slot, value) => {
+                slots[slot] = value;
+                continue;
+            }
+        };
+
+        // A path that has not started yet can only start at `sp` or later, so once there is a
+        // match, jobs that would start to the right of it cannot beat it.
+        if let Some(best) = matched.as_ref().and_then(|m| group_range(m, 0)) {
+            if slots.first().copied().flatten().unwrap_or(sp) > best.start {
+                continue;
+            }
+        }
 
-        match next {
-            Instruction::Char(c) => {
-                if let Some(sp_c) = line.get(sp) {
-                    if *c == *sp_c {
+        loop {
+            // display_context(inst, line, pc, sp);
+            let next = inst.get(pc).ok_or(EvalError::InvalidPC)?;
+            if !seen.insert(pc, sp, &slots) {
+                break;
+            }
+
+            match next {
+                Instruction::Char(_)
+                | Instruction::AnyChar
+                | Instruction::AnyCharExceptNewline
+                | Instruction::Class(_) => {
+                    if let Some(len) = consumes(next, line, sp) {
                         pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
-                        sp = sp.checked_add(1).ok_or(EvalError::SPOverFlow)?;
+                        sp = sp.checked_add(len).ok_or(EvalError::SPOverFlow)?;
                     } else {
-                        return Ok(false);
+                        break;
                     }
-                } else {
-                    return Ok(false);
                 }
-            }
-            Instruction::AnyChar => {
-                if let Some(_) = line.get(sp) {
-                    pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
-                    sp = sp.checked_add(1).ok_or(EvalError::SPOverFlow)?;
-                } else {
-                    return Ok(false);
+                Instruction::IsHead
+                | Instruction::IsTail
+                | Instruction::IsWordBoundary
+                | Instruction::IsNotWordBoundary
+                | Instruction::IsLineStart(_)
+                | Instruction::IsLineEnd(_) => {
+                    if asserts(next, line, sp) {
+                        pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
+                    } else {
+                        break;
+                    }
                 }
-            }
-            Instruction::Start => {
-                pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
-            }
-            Instruction::IsHead => {
-                if sp == 0 {
+                Instruction::Backref(group, case_insensitive) => {
+                    if let Some(len) = backref_len(line, &slots, *group, *case_insensitive, sp) {
+                        pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
+                        sp = sp.checked_add(len).ok_or(EvalError::SPOverFlow)?;
+                    } else {
+                        break;
+                    }
+                }
+                Instruction::Start => {
+                    save_with_restore(&mut stack, &mut slots, 0, sp)?;
                     pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
-                } else {
-                    return Ok(false);
                 }
-            }
-            Instruction::IsTail => {
-                if sp == line.len() {
+                Instruction::Save(slot) => {
+                    save_with_restore(&mut stack, &mut slots, *slot, sp)?;
                     pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
-                } else {
-                    return Ok(false);
                 }
-            }
-            Instruction::Match => return Ok(true),
-            Instruction::Jump(i) => {
-                pc = *i;
-            }
-            Instruction::Split(branch1, branch2) => {
-                return if eval_depth(inst, line, *branch1, sp)? {
-                    Ok(true)
-                } else {
-                    eval_depth(inst, line, *branch2, sp)
+                Instruction::LookAround(look) => {
+                    let Some(found) = look_holds(inst, line, look, pc, sp, &slots)? else {
+                        break;
+                    };
+                    for (slot, value) in found.into_iter().enumerate() {
+                        if let Some(value) = value.filter(|_| value != slots[slot]) {
+                            save_with_restore(&mut stack, &mut slots, slot, value)?;
+                        }
+                    }
+                    pc = look.end;
+                }
+                Instruction::LookMatch => {
+                    let Goal::Look(end) = goal else {
+                        return Err(EvalError::InvalidContext);
+                    };
+                    if end.is_none_or(|end| end == sp) {
+                        return Ok(Some(slots));
+                    }
+                    break;
+                }
+                Instruction::Match => {
+                    let Goal::Match(kind) = goal else {
+                        return Err(EvalError::InvalidContext);
+                    };
+
+                    let candidate = finish(slots.clone(), sp)?;
+                    match kind {
+                        MatchKind::LeftmostFirst => return Ok(Some(candidate)),
+                        MatchKind::LeftmostLongest => {
+                            if is_leftmost_longer(&matched, &candidate) {
+                                matched = Some(candidate);
+                            }
+                            break;
+                        }
+                    }
+                }
+                Instruction::Jump(i) => {
+                    pc = *i;
+                }
+                Instruction::Split(branch1, branch2) => {
+                    stack.push(Backtrack::Explore(*branch2, sp));
+                    pc = *branch1;
+                }
+                Instruction::Nop => {
+                    return Err(EvalError::AttemptNop);
                 }
-            }
-            Instruction::Nop => {
-                return Err(EvalError::AttemptNop);
             }
         }
     }
+
+    Ok(matched)
 }
 
-#[allow(unused_variables, unused_mut)]
-fn eval_width(inst: &[Instruction], line: &[char]) -> Result<bool, EvalError> {
-    let mut queue = VecDeque::new();
-    queue.push_back((0, 0));
+fn eval_width<I: Input + ?Sized>(
+    inst: &[Instruction],
+    line: &I,
+    slots: Vec<Option<usize>>,
+) -> Result<Option<usize>, EvalError> {
+    let kind = MatchKind::LeftmostFirst;
+    Ok(eval_pike_vm(inst, line, 0, slots, kind, true)?.and_then(|slots| slots[1]))
+}
 
-    loop {
-        if let Some((pc, sp)) = queue.pop_front() {
-            // display_context(inst, line, pc, sp);
-            let next = inst.get(pc).ok_or(EvalError::InvalidPC)?;
+/// A set of pcs that is cleared in O(1) and iterated in insertion order.
+struct SparseSet {
+    dense: Vec<usize>,
+    sparse: Vec<usize>,
+}
 
-            match next {
-                Instruction::Char(c) => {
-                    if let Some(sp_c) = line.get(sp) {
-                        if *c == *sp_c {
-                            let next_pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
-                            let next_sp = sp.checked_add(1).ok_or(EvalError::SPOverFlow)?;
+impl SparseSet {
+    fn new(capacity: usize) -> Self {
+        Self {
+            dense: Vec::with_capacity(capacity),
+            sparse: vec![0; capacity],
+        }
+    }
 
-                            queue.push_back((next_pc, next_sp));
-                        }
-                    }
-                }
-                Instruction::AnyChar => {
-                    if let Some(_) = line.get(sp) {
-                        let next_pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
-                        let next_sp = sp.checked_add(1).ok_or(EvalError::SPOverFlow)?;
+    fn contains(&self, value: usize) -> bool {
+        let i = self.sparse[value];
+        i < self.dense.len() && self.dense[i] == value
+    }
 
-                        queue.push_back((next_pc, next_sp));
-                    }
-                }
-                Instruction::Start => {
-                    let next_pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
+    fn insert(&mut self, value: usize) -> bool {
+        if self.contains(value) {
+            return false;
+        }
 
-                    queue.push_back((next_pc, sp));
-                }
-                Instruction::IsHead => {
-                    if sp == 0 {
-                        let next_pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
-                        queue.push_back((next_pc, sp));
-                    }
+        self.sparse[value] = self.dense.len();
+        self.dense.push(value);
+        true
+    }
+
+    fn is_empty(&self) -> bool {
+        self.dense.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.dense.clear();
+    }
+}
+
+enum Frame {
+    Explore(usize),
+    RestoreSlot(usize, Option<usize>),
+}
+
+/// Saves `sp` into `caps[slot]` before exploring `pc + 1`, and restores the slot afterwards.
+fn save_and_explore(
+    caps: &mut [Option<usize>],
+    stack: &mut Vec<Frame>,
+    slot: usize,
+    pc: usize,
+    sp: usize,
+) -> Result<(), EvalError> {
+    let prev = *caps.get(slot).ok_or(EvalError::InvalidSlot)?;
+    caps[slot] = Some(sp);
+
+    let next_pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
+    stack.push(Frame::RestoreSlot(slot, prev));
+    stack.push(Frame::Explore(next_pc));
+
+    Ok(())
+}
+
+/// The threads alive at one position of the line, at most one per pc.
+/// The capture slots of the thread at `pc` are `slots[pc * slots_len..(pc + 1) * slots_len]`.
+struct Threads {
+    pcs: SparseSet,
+    slots: Vec<Option<usize>>,
+    slots_len: usize,
+}
+
+impl Threads {
+    fn new(len: usize, slots_len: usize) -> Self {
+        Self {
+            pcs: SparseSet::new(len),
+            slots: vec![None; len * slots_len],
+            slots_len,
+        }
+    }
+
+    fn slots(&self, pc: usize) -> &[Option<usize>] {
+        &self.slots[pc * self.slots_len..(pc + 1) * self.slots_len]
+    }
+
+    /// Adds a thread at `pc` and follows every instruction that does not consume a char.
+    /// Threads are added in priority order, so a pc already taken keeps its first thread.
+    /// `caps` is used as scratch space and is left as it was given.
+    fn add<I: Input + ?Sized>(
+        &mut self,
+        inst: &[Instruction],
+        line: &I,
+        pc: usize,
+        sp: usize,
+        caps: &mut [Option<usize>],
+        stack: &mut Vec<Frame>,
+    ) -> Result<(), EvalError> {
+        stack.push(Frame::Explore(pc));
+
+        while let Some(frame) = stack.pop() {
+            let pc = match frame {
+                Frame::Explore(pc) => pc,
+                Frame::RestoreSlot(slot, value) => {
+                    caps[slot] = value;
+                    continue;
                 }
-                Instruction::IsTail => {
-                    if sp == line.len() {
+            };
+
+            let next = inst.get(pc).ok_or(EvalError::InvalidPC)?;
+            if !self.pcs.insert(pc) {
+                continue;
+            }
+
+            match next {
+                Instruction::Start => save_and_explore(caps, stack, 0, pc, sp)?,
+                Instruction::Save(slot) => save_and_explore(caps, stack, *slot, pc, sp)?,
+                Instruction::IsHead
+                | Instruction::IsTail
+                | Instruction::IsWordBoundary
+                | Instruction::IsNotWordBoundary
+                | Instruction::IsLineStart(_)
+                | Instruction::IsLineEnd(_) => {
+                    if asserts(next, line, sp) {
                         let next_pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
-                        queue.push_back((next_pc, sp));
+                        stack.push(Frame::Explore(next_pc));
                     }
                 }
-                Instruction::Match => return Ok(true),
                 Instruction::Jump(i) => {
-                    queue.push_back((*i, sp));
+                    stack.push(Frame::Explore(*i));
                 }
                 Instruction::Split(branch1, branch2) => {
-                    queue.push_back((*branch1, sp));
-                    queue.push_back((*branch2, sp));
+                    stack.push(Frame::Explore(*branch2));
+                    stack.push(Frame::Explore(*branch1));
+                }
+                Instruction::Backref(..) => {
+                    return Err(EvalError::UnsupportedBackref);
+                }
+                Instruction::LookAround(_) | Instruction::LookMatch => {
+                    return Err(EvalError::UnsupportedLookAround);
                 }
                 Instruction::Nop => {
                     return Err(EvalError::AttemptNop);
                 }
+                Instruction::Char(_)
+                | Instruction::AnyChar
+                | Instruction::AnyCharExceptNewline
+                | Instruction::Class(_)
+                | Instruction::Match => {
+                    let len = self.slots_len;
+                    self.slots[pc * len..(pc + 1) * len].copy_from_slice(caps);
+                }
             }
-        } else {
-            return Ok(false);
         }
+
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.pcs.clear();
     }
 }
 
-fn display_context(inst: &[Instruction], line: &[char], pc: usize, sp: usize) {
-    for c in line {
-        print!("{} ", c);
+/// Runs the program as a Pike VM, stepping every thread one char at a time. All threads of a
+/// step sit at the same position, so they all move on to the end of the same char.
+/// Threads are ordered by priority, and threads that started earlier come first.
+/// For leftmost-first, the threads after a matching one have lower priority and are dropped.
+/// For leftmost-longest, only the threads that started after the match are dropped, and the
+/// rest may still find a longer match. With `shortest` it returns at the first match instead,
+/// which is the one that ends earliest.
+fn eval_pike_vm<I: Input + ?Sized>(
+    inst: &[Instruction],
+    line: &I,
+    start: usize,
+    mut caps: Vec<Option<usize>>,
+    kind: MatchKind,
+    shortest: bool,
+) -> Result<Option<Vec<Option<usize>>>, EvalError> {
+    if has_backrefs(inst) {
+        return Err(EvalError::UnsupportedBackref);
     }
-    println!("\\0");
-    for (i, _) in line.iter().enumerate() {
-        if i == sp {
-            print!("^ ");
-        } else {
-            print!("  ");
+    if has_lookaround(inst) {
+        return Err(EvalError::UnsupportedLookAround);
+    }
+
+    let mut clist = Threads::new(inst.len(), caps.len());
+    let mut nlist = Threads::new(inst.len(), caps.len());
+    let mut stack = vec![];
+    let mut matched: Option<Vec<Option<usize>>> = None;
+
+    clist.add(inst, line, 0, start, &mut caps, &mut stack)?;
+
+    let mut sp = start;
+    loop {
+        let mut next_sp = sp;
+
+        for &pc in clist.pcs.dense.iter() {
+            let next = &inst[pc];
+            if !matches!(
+                next,
+                Instruction::Char(_)
+                    | Instruction::AnyChar
+                    | Instruction::AnyCharExceptNewline
+                    | Instruction::Class(_)
+                    | Instruction::Match
+            ) {
+                continue;
+            }
+
+            if let Some(best) = matched.as_ref().and_then(|m| group_range(m, 0)) {
+                if clist.slots(pc)[0].is_none_or(|start| start > best.start) {
+                    break;
+                }
+            }
+
+            if let Instruction::Match = next {
+                let candidate = finish(clist.slots(pc).to_vec(), sp)?;
+                if shortest {
+                    return Ok(Some(candidate));
+                }
+
+                match kind {
+                    MatchKind::LeftmostFirst => {
+                        matched = Some(candidate);
+                        break;
+                    }
+                    MatchKind::LeftmostLongest => {
+                        if is_leftmost_longer(&matched, &candidate) {
+                            matched = Some(candidate);
+                        }
+                        continue;
+                    }
+                }
+            }
+
+            if let Some(len) = consumes(next, line, sp) {
+                let next_pc = pc.checked_add(1).ok_or(EvalError::PCOverFlow)?;
+                next_sp = sp.checked_add(len).ok_or(EvalError::SPOverFlow)?;
+
+                caps.copy_from_slice(clist.slots(pc));
+                nlist.add(inst, line, next_pc, next_sp, &mut caps, &mut stack)?;
+            }
+        }
+
+        if nlist.pcs.is_empty() {
+            break;
         }
+        std::mem::swap(&mut clist, &mut nlist);
+        nlist.clear();
+        sp = next_sp;
     }
+
+    Ok(matched)
+}
+
+#[allow(dead_code)]
+fn display_context<I: Input + ?Sized>(inst: &[Instruction], line: &I, pc: usize, sp: usize) {
+    let mut cursor = String::new();
+    let mut at = 0;
+    while let Some((c, len)) = line.char_at(at) {
+        print!("{} ", c);
+        cursor.push_str(if at == sp { "^ " } else { "  " });
+        at += len;
+    }
+    println!("\\0");
     if sp == line.len() {
-        print!("^ ")
+        cursor.push_str("^ ");
     }
-    println!();
+    println!("{}", cursor);
 
     for (i, ins) in inst.iter().enumerate() {
         if i == pc {
@@ -0,0 +1,102 @@
+/// A haystack the evaluators read one char at a time, at byte positions.
+///
+/// Positions handed out by the evaluators always sit on char boundaries, so the offsets of a
+/// match can be used to slice the original input.
+pub trait Input {
+    /// Length of the input in bytes.
+    fn len(&self) -> usize;
+
+    /// Decodes the char starting at byte `at`, along with its length in bytes.
+    fn char_at(&self, at: usize) -> Option<(char, usize)>;
+
+    /// Decodes the char ending right before byte `at`, along with its length in bytes.
+    fn char_before(&self, at: usize) -> Option<(char, usize)>;
+}
+
+/// UTF-8 text, decoded on the fly.
+impl Input for str {
+    fn len(&self) -> usize {
+        str::len(self)
+    }
+
+    fn char_at(&self, at: usize) -> Option<(char, usize)> {
+        let c = self.get(at..)?.chars().next()?;
+        Some((c, c.len_utf8()))
+    }
+
+    fn char_before(&self, at: usize) -> Option<(char, usize)> {
+        let c = self.get(..at)?.chars().next_back()?;
+        Some((c, c.len_utf8()))
+    }
+}
+
+/// Arbitrary bytes, where valid UTF-8 sequences are read as the chars they encode and every
+/// other byte is read on its own as U+FFFD, so that data that is not UTF-8 can be searched
+/// without bytes of a broken sequence matching as if they were chars of their own.
+impl Input for [u8] {
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+
+    fn char_at(&self, at: usize) -> Option<(char, usize)> {
+        let bytes = self.get(at..self.len().min(at + 4))?;
+        let valid = match std::str::from_utf8(bytes) {
+            Ok(valid) => valid,
+            Err(e) => std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+        };
+
+        match valid.chars().next() {
+            Some(c) => Some((c, c.len_utf8())),
+            None if bytes.is_empty() => None,
+            None => Some((char::REPLACEMENT_CHARACTER, 1)),
+        }
+    }
+
+    fn char_before(&self, at: usize) -> Option<(char, usize)> {
+        let bytes = self.get(..at)?;
+        if bytes.is_empty() {
+            return None;
+        }
+
+        for len in 1..=bytes.len().min(4) {
+            if let Ok(valid) = std::str::from_utf8(&bytes[bytes.len() - len..]) {
+                return valid.chars().next().map(|c| (c, len));
+            }
+        }
+        Some((char::REPLACEMENT_CHARACTER, 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn str_input() {
+        let line = "aé😀";
+        assert_eq!(Input::len(line), 7);
+        assert_eq!(line.char_at(0), Some(('a', 1)));
+        assert_eq!(line.char_at(1), Some(('é', 2)));
+        assert_eq!(line.char_at(3), Some(('😀', 4)));
+        assert_eq!(line.char_at(7), None);
+        assert_eq!(line.char_before(3), Some(('é', 2)));
+        assert_eq!(line.char_before(0), None);
+    }
+
+    #[test]
+    fn bytes_input() {
+        let line: &[u8] = b"a\xFF\xC3\xA9\xA9\xE2\x82";
+        assert_eq!(Input::len(line), 7);
+        assert_eq!(line.char_at(1), Some(('\u{FFFD}', 1)));
+        assert_eq!(line.char_at(2), Some(('é', 2)));
+        assert_eq!(line.char_at(3), Some(('\u{FFFD}', 1)));
+        assert_eq!(line.char_at(4), Some(('\u{FFFD}', 1)));
+        assert_eq!(line.char_at(5), Some(('\u{FFFD}', 1)));
+        assert_eq!(line.char_at(7), None);
+        assert_eq!(line.char_before(2), Some(('\u{FFFD}', 1)));
+        assert_eq!(line.char_before(4), Some(('é', 2)));
+        assert_eq!(line.char_before(5), Some(('\u{FFFD}', 1)));
+        assert_eq!(line.char_before(7), Some(('\u{FFFD}', 1)));
+        assert_eq!(line.char_before(0), None);
+    }
+}
@@ -1,44 +1,865 @@
-use crate::engine::parser::ParserError::InvalidEscape;
+use crate::engine::unicode_tables::{general_category, property_bool};
+use crate::engine::{
+    case_variants, ranges_contain, unicode_property, CharClass, DIGIT_RANGES, SPACE_RANGES,
+    WORD_RANGES,
+};
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
+use std::iter::Peekable;
 use std::mem::take;
+use std::ops::Range;
+use std::str::CharIndices;
 
 #[derive(Debug, PartialEq)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum AST {
     Char(char),
+    /// `.` in dot-all mode.
     AnyChar,
-    Dollar(Box<AST>),
-    Hat(Box<AST>),
-    Plus(Box<AST>),
-    Star(Box<AST>),
-    Question(Box<AST>),
+    /// `.`, which does not match `\n` unless dot-all mode is on.
+    AnyCharExceptNewline,
+    Class(CharClass),
+    WordBoundary,
+    NotWordBoundary,
+    /// `^` in multi-line mode. The flag tells whether `\r\n` also ends a line.
+    LineStart(bool),
+    /// `$` in multi-line mode. The flag tells whether `\r\n` also ends a line.
+    LineEnd(bool),
+    /// `^` outside multi-line mode, or `\A`.
+    Hat,
+    /// `$` outside multi-line mode, or `\z`.
+    Dollar,
+    Plus(Box<AST>, bool),
+    Star(Box<AST>, bool),
+    Question(Box<AST>, bool),
+    Repeat {
+        ast: Box<AST>,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
     Or(Box<AST>, Box<AST>),
     Seq(Vec<AST>),
+    /// A capturing group with its index and, if it has one, its name.
+    Capture(usize, Option<String>, Box<AST>),
+    /// `\1` or `\k<name>`, by group index, and whether it ignores case.
+    Backref(usize, bool),
+    /// `(?=...)`, or `(?!...)` when not positive.
+    Lookahead(Box<AST>, bool),
+    /// `(?<=...)`, or `(?<!...)` when not positive.
+    Lookbehind(Box<AST>, bool),
 }
 
+impl AST {
+    /// Number of capture groups in the expression, including the implicit group 0.
+    pub fn captures_len(&self) -> usize {
+        match self {
+            AST::Char(_)
+            | AST::AnyChar
+            | AST::AnyCharExceptNewline
+            | AST::Class(_)
+            | AST::WordBoundary
+            | AST::NotWordBoundary
+            | AST::LineStart(_)
+            | AST::LineEnd(_)
+            | AST::Hat
+            | AST::Dollar
+            | AST::Backref(..) => 1,
+            AST::Plus(ast, _)
+            | AST::Star(ast, _)
+            | AST::Question(ast, _)
+            | AST::Repeat { ast, .. }
+            | AST::Lookahead(ast, _)
+            | AST::Lookbehind(ast, _) => ast.captures_len(),
+            AST::Or(ast1, ast2) => ast1.captures_len().max(ast2.captures_len()),
+            AST::Seq(asts) => asts.iter().map(AST::captures_len).max().unwrap_or(1),
+            AST::Capture(index, _, ast) => (index + 1).max(ast.captures_len()),
+        }
+    }
+
+    /// The name of every capture group by index, `None` for group 0 and unnamed groups.
+    pub fn capture_names(&self) -> Vec<Option<String>> {
+        let mut names = vec![None; self.captures_len()];
+        self.collect_names(&mut names);
+        names
+    }
+
+    /// The least and the most chars the expression can match, `None` if there is no bound.
+    pub fn len_bounds(&self) -> (usize, Option<usize>) {
+        match self {
+            AST::Char(_) | AST::AnyChar | AST::AnyCharExceptNewline | AST::Class(_) => (1, Some(1)),
+            AST::WordBoundary
+            | AST::NotWordBoundary
+            | AST::LineStart(_)
+            | AST::LineEnd(_)
+            | AST::Hat
+            | AST::Dollar
+            | AST::Lookahead(..)
+            | AST::Lookbehind(..) => (0, Some(0)),
+            AST::Backref(..) => (0, None),
+            AST::Plus(ast, _) => (ast.len_bounds().0, repeat_bound(ast, None)),
+            AST::Star(ast, _) => (0, repeat_bound(ast, None)),
+            AST::Question(ast, _) => (0, ast.len_bounds().1),
+            AST::Repeat { ast, min, max, .. } => (
+                ast.len_bounds().0.saturating_mul(*min),
+                repeat_bound(ast, *max),
+            ),
+            AST::Or(ast1, ast2) => {
+                let (min1, max1) = ast1.len_bounds();
+                let (min2, max2) = ast2.len_bounds();
+                (min1.min(min2), max1.zip(max2).map(|(a, b)| a.max(b)))
+            }
+            AST::Seq(asts) => asts.iter().fold((0, Some(0)), |(min, max), ast| {
+                let (ast_min, ast_max) = ast.len_bounds();
+                (
+                    min.saturating_add(ast_min),
+                    max.zip(ast_max).and_then(|(a, b)| a.checked_add(b)),
+                )
+            }),
+            AST::Capture(_, _, ast) => ast.len_bounds(),
+        }
+    }
+
+    fn collect_names(&self, names: &mut [Option<String>]) {
+        match self {
+            AST::Plus(ast, _)
+            | AST::Star(ast, _)
+            | AST::Question(ast, _)
+            | AST::Repeat { ast, .. }
+            | AST::Lookahead(ast, _)
+            | AST::Lookbehind(ast, _) => ast.collect_names(names),
+            AST::Or(ast1, ast2) => {
+                ast1.collect_names(names);
+                ast2.collect_names(names);
+            }
+            AST::Seq(asts) => asts.iter().for_each(|ast| ast.collect_names(names)),
+            AST::Capture(index, name, ast) => {
+                names[*index] = name.clone();
+                ast.collect_names(names);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// An error in a pattern. The first field is the byte range of the pattern it is about.
 #[derive(Debug)]
 pub enum ParserError {
-    InvalidEscape(usize, char),
-    InvalidHat,
-    InvalidDollar,
-    NoPrev(usize),
-    NoRightParen,
-    Empty,
+    InvalidEscape(Range<usize>, char),
+    /// At a `\` that ends the pattern.
+    TrailingEscape(Range<usize>),
+    NoPrev(Range<usize>),
+    /// At the `(` of the group left open.
+    NoRightParen(Range<usize>),
+    UnmatchedRightParen(Range<usize>),
+    /// At the `[` of the class left open.
+    NoRightBracket(Range<usize>),
+    InvalidRange(Range<usize>, char, char),
+    InvalidRepeat(Range<usize>),
+    InvalidRepeatRange(Range<usize>, usize, usize),
+    InvalidFlag(Range<usize>, char),
+    /// At the `)` of a flag group with no flag, or at the `)` or `:` right after its `-`.
+    MissingFlag(Range<usize>),
+    InvalidGroupName(Range<usize>),
+    DuplicateGroupName(Range<usize>, String),
+    InvalidBackref(Range<usize>),
+    UnboundedLookbehind(Range<usize>),
+    InvalidHex(Range<usize>),
+    InvalidCodePoint(Range<usize>, u32),
+    InvalidProperty(Range<usize>),
+    UnknownProperty(Range<usize>, String),
+    UnknownPosixClass(Range<usize>, String),
+    Empty(Range<usize>),
+}
+
+impl ParserError {
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            ParserError::InvalidEscape(span, _)
+            | ParserError::TrailingEscape(span)
+            | ParserError::NoPrev(span)
+            | ParserError::NoRightParen(span)
+            | ParserError::UnmatchedRightParen(span)
+            | ParserError::NoRightBracket(span)
+            | ParserError::InvalidRange(span, _, _)
+            | ParserError::InvalidRepeat(span)
+            | ParserError::InvalidRepeatRange(span, _, _)
+            | ParserError::InvalidFlag(span, _)
+            | ParserError::MissingFlag(span)
+            | ParserError::InvalidGroupName(span)
+            | ParserError::DuplicateGroupName(span, _)
+            | ParserError::InvalidBackref(span)
+            | ParserError::UnboundedLookbehind(span)
+            | ParserError::InvalidHex(span)
+            | ParserError::InvalidCodePoint(span, _)
+            | ParserError::InvalidProperty(span)
+            | ParserError::UnknownProperty(span, _)
+            | ParserError::UnknownPosixClass(span, _)
+            | ParserError::Empty(span) => span.clone(),
+        }
+    }
+
+    /// What is wrong with the part of the pattern at `span`, to print next to it.
+    pub fn label(&self) -> String {
+        match self {
+            ParserError::InvalidEscape(..) => "unrecognized escape sequence".to_string(),
+            ParserError::TrailingEscape(_) => "nothing follows this `\\`".to_string(),
+            ParserError::NoPrev(_) => "nothing before this to apply it to".to_string(),
+            ParserError::NoRightParen(_) => "unclosed group opened here".to_string(),
+            ParserError::UnmatchedRightParen(_) => "this `)` closes no group".to_string(),
+            ParserError::NoRightBracket(_) => "unclosed class opened here".to_string(),
+            ParserError::InvalidRange(..) => "range start is after its end".to_string(),
+            ParserError::InvalidRepeat(_) => "expected `{n}`, `{n,}` or `{n,m}`".to_string(),
+            ParserError::InvalidRepeatRange(_, min, max) => {
+                format!("minimum {} is greater than maximum {}", min, max)
+            }
+            ParserError::InvalidFlag(..) | ParserError::MissingFlag(_) => {
+                "expected one of `i`, `m`, `s` or `R`".to_string()
+            }
+            ParserError::InvalidGroupName(_) => {
+                "expected a letter or `_`, then letters, digits or `_`, then `>`".to_string()
+            }
+            ParserError::DuplicateGroupName(..) => "this name is already taken".to_string(),
+            ParserError::InvalidBackref(_) => "no group before this has that name".to_string(),
+            ParserError::UnboundedLookbehind(_) => {
+                "lookbehind has to match a bounded number of chars".to_string()
+            }
+            ParserError::InvalidHex(_) => "expected hex digits".to_string(),
+            ParserError::InvalidCodePoint(_, code) => {
+                format!("U+{:X} is not a Unicode scalar value", code)
+            }
+            ParserError::InvalidProperty(_) => {
+                "expected a one-letter name or a name in braces".to_string()
+            }
+            ParserError::UnknownProperty(..) => {
+                "no general category, script or binary property has this name".to_string()
+            }
+            ParserError::UnknownPosixClass(..) => "not a POSIX class".to_string(),
+            ParserError::Empty(_) => "nothing to match".to_string(),
+        }
+    }
+
+    /// A suggestion for fixing the pattern, if there is an obvious one.
+    pub fn help(&self) -> Option<String> {
+        match self {
+            ParserError::NoPrev(_) => Some("escape it with `\\` to match it literally".to_string()),
+            ParserError::TrailingEscape(_) => {
+                Some("escape it as `\\\\` to match a literal `\\`".to_string())
+            }
+            ParserError::NoRightParen(_) => Some("add a `)` to close it".to_string()),
+            ParserError::UnmatchedRightParen(_) => {
+                Some("escape it as `\\)` to match a literal `)`".to_string())
+            }
+            ParserError::NoRightBracket(_) => Some("add a `]` to close it".to_string()),
+            ParserError::InvalidRange(_, start, end) => {
+                Some(format!("write it as `{}-{}`", end, start))
+            }
+            ParserError::InvalidRepeat(_) => {
+                Some("escape it as `\\{` to match a literal `{`".to_string())
+            }
+            ParserError::InvalidRepeatRange(_, min, max) => {
+                Some(format!("write it as `{{{},{}}}`", max, min))
+            }
+            ParserError::UnboundedLookbehind(_) => {
+                Some("replace `*`, `+` and `{n,}` with bounded repetitions".to_string())
+            }
+            ParserError::UnknownPosixClass(_, name) => {
+                let similar = POSIX_CLASSES
+                    .iter()
+                    .min_by_key(|known| edit_distance(name, known))
+                    .filter(|known| edit_distance(name, known) <= 2)?;
+                Some(format!("did you mean `[:{}:]`?", similar))
+            }
+            _ => None,
+        }
+    }
+
+    /// Renders the error below `expr`, the pattern it was found in, with the part at `span`
+    /// underlined.
+    pub fn render(&self, expr: &str) -> String {
+        let span = self.span();
+        let column = display_width(&expr[..span.start]);
+        let width = display_width(&expr[span.clone()]).max(1);
+
+        let mut out = format!(
+            "regex parse error: {}\n    {}\n    {}^{} {}",
+            self,
+            expr.replace('\t', &" ".repeat(TAB_WIDTH)),
+            " ".repeat(column),
+            "~".repeat(width - 1),
+            self.label()
+        );
+        if let Some(help) = self.help() {
+            out.push_str(&format!("\nhelp: {}", help));
+        }
+        out
+    }
 }
 
 impl Display for ParserError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            ParserError::InvalidEscape(_, c) => write!(f, "invalid escape `\\{}`", c),
+            ParserError::TrailingEscape(_) => write!(f, "trailing escape"),
+            ParserError::NoPrev(_) => write!(f, "missing expression"),
+            ParserError::NoRightParen(_) => write!(f, "unclosed group"),
+            ParserError::UnmatchedRightParen(_) => write!(f, "unopened group"),
+            ParserError::NoRightBracket(_) => write!(f, "unclosed class"),
+            ParserError::InvalidRange(_, start, end) => {
+                write!(f, "invalid class range `{}-{}`", start, end)
+            }
+            ParserError::InvalidRepeat(_) => write!(f, "invalid repetition"),
+            ParserError::InvalidRepeatRange(..) => write!(f, "invalid repetition range"),
+            ParserError::InvalidFlag(_, c) => write!(f, "unknown flag `{}`", c),
+            ParserError::MissingFlag(_) => write!(f, "missing flag"),
+            ParserError::InvalidGroupName(_) => write!(f, "invalid group name"),
+            ParserError::DuplicateGroupName(_, name) => {
+                write!(f, "duplicate group name `{}`", name)
+            }
+            ParserError::InvalidBackref(_) => write!(f, "invalid backreference"),
+            ParserError::UnboundedLookbehind(_) => write!(f, "unbounded lookbehind"),
+            ParserError::InvalidHex(_) => write!(f, "invalid hex escape"),
+            ParserError::InvalidCodePoint(..) => write!(f, "invalid code point"),
+            ParserError::InvalidProperty(_) => write!(f, "invalid Unicode property"),
+            ParserError::UnknownProperty(_, name) => {
+                write!(f, "unknown Unicode property `{}`", name)
+            }
+            ParserError::UnknownPosixClass(_, name) => {
+                write!(f, "unknown POSIX class `{}`", name)
+            }
+            ParserError::Empty(_) => write!(f, "empty pattern"),
+        }
     }
 }
 
+/// The number of spaces a tab is expanded to when a pattern is rendered.
+const TAB_WIDTH: usize = 4;
+
+/// East Asian wide and fullwidth chars, which terminals draw two columns wide.
+const WIDE_RANGES: &[(char, char)] = &[
+    ('\u{1100}', '\u{115F}'),
+    ('\u{2E80}', '\u{303E}'),
+    ('\u{3041}', '\u{33FF}'),
+    ('\u{3400}', '\u{4DBF}'),
+    ('\u{4E00}', '\u{9FFF}'),
+    ('\u{A000}', '\u{A4CF}'),
+    ('\u{A960}', '\u{A97F}'),
+    ('\u{AC00}', '\u{D7A3}'),
+    ('\u{F900}', '\u{FAFF}'),
+    ('\u{FE10}', '\u{FE19}'),
+    ('\u{FE30}', '\u{FE6F}'),
+    ('\u{FF00}', '\u{FF60}'),
+    ('\u{FFE0}', '\u{FFE6}'),
+    ('\u{20000}', '\u{2FFFD}'),
+    ('\u{30000}', '\u{3FFFD}'),
+];
+
+/// The number of terminal columns `text` takes up once rendered, with tabs expanded.
+fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|c| match c {
+            '\t' => TAB_WIDTH,
+            _ if ranges_contain(general_category::NONSPACING_MARK, c)
+                || ranges_contain(general_category::ENCLOSING_MARK, c)
+                || ranges_contain(property_bool::DEFAULT_IGNORABLE_CODE_POINT, c) =>
+            {
+                0
+            }
+            _ if ranges_contain(WIDE_RANGES, c)
+                || ranges_contain(property_bool::EMOJI_PRESENTATION, c) =>
+            {
+                2
+            }
+            _ => 1,
+        })
+        .sum()
+}
+
+/// A `ParserError` together with the pattern it was found in, which `Display` renders with
+/// the offending part underlined.
+#[derive(Debug)]
+pub struct PatternError {
+    expr: String,
+    error: ParserError,
+}
+
+impl PatternError {
+    pub fn new(expr: &str, error: ParserError) -> Self {
+        Self {
+            expr: expr.to_string(),
+            error,
+        }
+    }
+
+    pub fn error(&self) -> &ParserError {
+        &self.error
+    }
+}
+
+impl Display for PatternError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error.render(&self.expr))
+    }
+}
+
+impl Error for PatternError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// The number of single-char insertions, deletions and substitutions turning `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            row.push((prev[j] + cost).min(prev[j + 1] + 1).min(row[j] + 1));
+        }
+        prev = row;
+    }
+
+    prev[b.len()]
+}
+
 impl Error for ParserError {}
 
-fn parse_escape(pos: usize, c: char) -> Result<AST, ParserError> {
+/// Returns the ranges of `\d`, `\w` or `\s`, and whether the upper case form negates them.
+fn perl_class(c: char) -> Option<(&'static [(char, char)], bool)> {
     match c {
-        '\\' | '(' | ')' | '|' | '*' | '+' | '?' | '.' | '^' | '$' => Ok(AST::Char(c)),
-        _ => Err(InvalidEscape(pos, c)),
+        'd' => Some((DIGIT_RANGES, false)),
+        'D' => Some((DIGIT_RANGES, true)),
+        'w' => Some((WORD_RANGES, false)),
+        'W' => Some((WORD_RANGES, true)),
+        's' => Some((SPACE_RANGES, false)),
+        'S' => Some((SPACE_RANGES, true)),
+        _ => None,
+    }
+}
+
+const POSIX_CLASSES: &[&str] = &[
+    "alnum", "alpha", "blank", "cntrl", "digit", "graph", "lower", "print", "punct", "space",
+    "upper", "xdigit",
+];
+
+/// Returns the ranges of a POSIX class such as `alpha` in `[[:alpha:]]`.
+fn posix_class(name: &str) -> Option<&'static [(char, char)]> {
+    let ranges: &[(char, char)] = match name {
+        "alnum" => &[('0', '9'), ('A', 'Z'), ('a', 'z')],
+        "alpha" => &[('A', 'Z'), ('a', 'z')],
+        "blank" => &[('\t', '\t'), (' ', ' ')],
+        "cntrl" => &[('\0', '\x1F'), ('\x7F', '\x7F')],
+        "digit" => DIGIT_RANGES,
+        "graph" => &[('!', '~')],
+        "lower" => &[('a', 'z')],
+        "print" => &[(' ', '~')],
+        "punct" => &[('!', '/'), (':', '@'), ('[', '`'), ('{', '~')],
+        "space" => SPACE_RANGES,
+        "upper" => &[('A', 'Z')],
+        "xdigit" => &[('0', '9'), ('A', 'F'), ('a', 'f')],
+        _ => return None,
+    };
+    Some(ranges)
+}
+
+/// The span of `\c`, where `pos` is the position of `c`.
+fn escape_span(pos: usize, c: char) -> Range<usize> {
+    pos - 1..pos + c.len_utf8()
+}
+
+/// Builds the class of `ranges`, folding case before negating, so that `(?i)[^a]` does not
+/// match `A` either.
+fn new_class(ranges: &[(char, char)], negated: bool, case_insensitive: bool) -> CharClass {
+    let mut class = CharClass::new(ranges.to_vec(), false);
+    if case_insensitive {
+        class = class.case_fold();
+    }
+    if negated {
+        class = class.negate();
+    }
+    class
+}
+
+fn parse_escape(pos: usize, c: char, case_insensitive: bool) -> Result<AST, ParserError> {
+    if let Some((ranges, negated)) = perl_class(c) {
+        return Ok(AST::Class(new_class(ranges, negated, case_insensitive)));
+    }
+
+    match c {
+        '\\' | '(' | ')' | '|' | '*' | '+' | '?' | '.' | '^' | '$' | '[' | ']' | '{' | '}' => {
+            Ok(AST::Char(c))
+        }
+        'b' => Ok(AST::WordBoundary),
+        'B' => Ok(AST::NotWordBoundary),
+        'A' => Ok(AST::Hat),
+        'z' => Ok(AST::Dollar),
+        _ => Err(ParserError::InvalidEscape(escape_span(pos, c), c)),
+    }
+}
+
+/// The chars of a pattern with their byte offsets.
+#[derive(Clone)]
+struct ExprChars<'a> {
+    chars: Peekable<CharIndices<'a>>,
+    len: usize,
+}
+
+impl<'a> ExprChars<'a> {
+    fn new(expr: &'a str) -> Self {
+        Self {
+            chars: expr.char_indices().peekable(),
+            len: expr.len(),
+        }
+    }
+
+    fn next_if(&mut self, func: impl FnOnce(&(usize, char)) -> bool) -> Option<(usize, char)> {
+        self.chars.next_if(func)
+    }
+
+    /// The offset of the next char, which is where the chars consumed so far end.
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.len, |(i, _)| *i)
+    }
+}
+
+impl Iterator for ExprChars<'_> {
+    type Item = (usize, char);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.chars.next()
+    }
+}
+
+/// Parses an escape that stands for a single character: `\n`, `\t`, `\r`, `\f`, `\v`, `\xHH`,
+/// `\x{H...}`, `\uHHHH` or `\u{H...}`. `pos` is the position of `c`, the character after `\`.
+/// Returns `None` if `c` starts some other escape.
+fn parse_char_escape(
+    chars: &mut ExprChars,
+    pos: usize,
+    c: char,
+) -> Result<Option<char>, ParserError> {
+    let digits = match c {
+        'n' => return Ok(Some('\n')),
+        't' => return Ok(Some('\t')),
+        'r' => return Ok(Some('\r')),
+        'f' => return Ok(Some('\x0C')),
+        'v' => return Ok(Some('\x0B')),
+        'x' => 2,
+        'u' => 4,
+        _ => return Ok(None),
+    };
+
+    let braced = chars.next_if(|(_, c)| *c == '{').is_some();
+    let mut code: u32 = 0;
+    let mut len = 0;
+    loop {
+        if !braced && len == digits {
+            break;
+        }
+        let d = chars.next().map(|(_, d)| d);
+        if braced && d == Some('}') && len > 0 {
+            break;
+        }
+        let digit = d
+            .and_then(|d| d.to_digit(16))
+            .ok_or_else(|| ParserError::InvalidHex(pos - 1..chars.offset()))?;
+        code = code.saturating_mul(16).saturating_add(digit);
+        len += 1;
+    }
+
+    char::from_u32(code)
+        .map(Some)
+        .ok_or_else(|| ParserError::InvalidCodePoint(pos - 1..chars.offset(), code))
+}
+
+/// Flags that change how the rest of the enclosing group is parsed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Flags {
+    /// `m`: `^` and `$` also match at the start and end of every line.
+    pub multi_line: bool,
+    /// `R`: lines also end with `\r\n` in multi-line mode.
+    pub crlf: bool,
+    /// `i`: letters match regardless of case.
+    pub case_insensitive: bool,
+    /// `s`: `.` also matches `\n`.
+    pub dot_all: bool,
+}
+
+/// Parses the rest of an inline flag group such as `(?m)`, `(?-m)` or `(?i:`, whose `(?`
+/// `chars` has consumed. Returns `flags` updated by it, and whether the flags only apply to the
+/// group that follows, as in `(?i:abc)`. `open_pos` is the position of `(`.
+fn parse_flags(
+    chars: &mut ExprChars,
+    mut flags: Flags,
+    open_pos: usize,
+) -> Result<(Flags, bool), ParserError> {
+    let mut enable = true;
+    let mut empty = true;
+
+    loop {
+        let (i, c) = chars
+            .next()
+            .ok_or(ParserError::NoRightParen(open_pos..open_pos + 1))?;
+        match c {
+            ')' if empty => return Err(ParserError::MissingFlag(i..i + 1)),
+            ':' if empty && !enable => return Err(ParserError::MissingFlag(i..i + 1)),
+            ')' => return Ok((flags, false)),
+            ':' => return Ok((flags, true)),
+            '-' if enable => {
+                enable = false;
+                empty = true;
+                continue;
+            }
+            'i' => flags.case_insensitive = enable,
+            's' => flags.dot_all = enable,
+            'm' => flags.multi_line = enable,
+            'R' => flags.crlf = enable,
+            _ => return Err(ParserError::InvalidFlag(i..i + c.len_utf8(), c)),
+        }
+        empty = false;
+    }
+}
+
+/// Parses the name after `\p` or `\P`, either a single letter or `{...}`, and returns the chars
+/// of that Unicode property. `pos` is the position of `p` or `P`.
+fn parse_property(chars: &mut ExprChars, pos: usize) -> Result<Vec<(char, char)>, ParserError> {
+    let (_, c) = chars
+        .next()
+        .ok_or(ParserError::InvalidProperty(pos - 1..pos + 1))?;
+    let name = if c == '{' {
+        let mut name = String::new();
+        loop {
+            match chars.next() {
+                Some((_, '}')) if !name.is_empty() => break,
+                Some((_, '}')) | None => {
+                    return Err(ParserError::InvalidProperty(pos - 1..chars.offset()))
+                }
+                Some((_, c)) => name.push(c),
+            }
+        }
+        name
+    } else {
+        c.to_string()
+    };
+
+    unicode_property(&name)
+        .ok_or_else(|| ParserError::UnknownProperty(pos - 1..chars.offset(), name))
+}
+
+/// Parses a POSIX class such as `[:alpha:]` or `[:^space:]` inside a bracket expression.
+/// `pos` is the position of its `[`, which `chars` has consumed. Returns `None`, consuming
+/// nothing, if no `:]` closes it, in which case the `[` is an ordinary char.
+fn parse_posix_class(
+    chars: &mut ExprChars,
+    pos: usize,
+    case_insensitive: bool,
+) -> Result<Option<CharClass>, ParserError> {
+    let mut ahead = chars.clone();
+    if ahead.next_if(|(_, c)| *c == ':').is_none() {
+        return Ok(None);
+    }
+    let negated = ahead.next_if(|(_, c)| *c == '^').is_some();
+
+    let mut name = String::new();
+    while let Some((_, c)) = ahead.next_if(|(_, c)| c.is_ascii_alphabetic()) {
+        name.push(c);
+    }
+    if !matches!(
+        (ahead.next(), ahead.next()),
+        (Some((_, ':')), Some((_, ']')))
+    ) {
+        return Ok(None);
+    }
+    *chars = ahead;
+
+    let ranges = posix_class(&name)
+        .ok_or_else(|| ParserError::UnknownPosixClass(pos..chars.offset(), name))?;
+    Ok(Some(new_class(ranges, negated, case_insensitive)))
+}
+
+enum ClassAtom {
+    Char(char),
+    Class(CharClass),
+}
+
+fn parse_class_escape(
+    chars: &mut ExprChars,
+    pos: usize,
+    c: char,
+    case_insensitive: bool,
+) -> Result<ClassAtom, ParserError> {
+    if let Some(c) = parse_char_escape(chars, pos, c)? {
+        return Ok(ClassAtom::Char(c));
+    }
+    if c == 'p' || c == 'P' {
+        let ranges = parse_property(chars, pos)?;
+        return Ok(ClassAtom::Class(new_class(
+            &ranges,
+            c == 'P',
+            case_insensitive,
+        )));
+    }
+    if let Some((ranges, negated)) = perl_class(c) {
+        return Ok(ClassAtom::Class(new_class(
+            ranges,
+            negated,
+            case_insensitive,
+        )));
+    }
+
+    match c {
+        '\\' | '[' | ']' | '^' | '-' | '&' => Ok(ClassAtom::Char(c)),
+        _ => Err(ParserError::InvalidEscape(escape_span(pos, c), c)),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ClassOp {
+    Intersection,
+    Difference,
+}
+
+/// Parses a bracket expression. `open_pos` is the position of `[`, which `chars` has consumed.
+/// A `]` right after `[` or `[^`, and a `-` at either end, are taken literally. Items may be
+/// nested classes, and `&&` and `--` intersect with or subtract the items that follow them,
+/// left to right, as in `[\w--\d]` or `[a-z&&[^aeiou]]`.
+fn parse_class(
+    chars: &mut ExprChars,
+    open_pos: usize,
+    case_insensitive: bool,
+) -> Result<CharClass, ParserError> {
+    let negated = chars.next_if(|(_, c)| *c == '^').is_some();
+    let mut class = CharClass::new(vec![], false);
+    let mut operand = CharClass::new(vec![], false);
+    let mut op = None;
+    let mut first = true;
+
+    loop {
+        let (i, c) = chars
+            .next()
+            .ok_or(ParserError::NoRightBracket(open_pos..open_pos + 1))?;
+        let is_first = std::mem::replace(&mut first, false);
+
+        let next_op = match c {
+            '&' => chars
+                .next_if(|(_, c)| *c == '&')
+                .map(|_| ClassOp::Intersection),
+            '-' => chars
+                .next_if(|(_, c)| *c == '-')
+                .map(|_| ClassOp::Difference),
+            _ => None,
+        };
+        if (c == ']' && !is_first) || next_op.is_some() {
+            class = match op {
+                None => operand,
+                Some(ClassOp::Intersection) => class.intersection(&operand),
+                Some(ClassOp::Difference) => class.difference(&operand),
+            };
+            operand = CharClass::new(vec![], false);
+            op = next_op;
+            if c == ']' {
+                break;
+            }
+            continue;
+        }
+
+        let start = match c {
+            '[' => {
+                let nested = match parse_posix_class(chars, i, case_insensitive)? {
+                    Some(nested) => nested,
+                    None => parse_class(chars, i, case_insensitive)?,
+                };
+                operand = operand.union(&nested);
+                continue;
+            }
+            '\\' => {
+                let (j, e) = chars
+                    .next()
+                    .ok_or(ParserError::NoRightBracket(open_pos..open_pos + 1))?;
+                match parse_class_escape(chars, j, e, case_insensitive)? {
+                    ClassAtom::Char(c) => c,
+                    ClassAtom::Class(escaped) => {
+                        operand = operand.union(&escaped);
+                        continue;
+                    }
+                }
+            }
+            _ => c,
+        };
+
+        let mut ahead = chars.clone();
+        let is_range = matches!(ahead.next(), Some((_, '-')))
+            && !matches!(ahead.next(), Some((_, ']' | '-')) | None);
+
+        let end = if is_range {
+            chars.next();
+            match chars.next() {
+                Some((_, '\\')) => {
+                    let (j, e) = chars
+                        .next()
+                        .ok_or(ParserError::NoRightBracket(open_pos..open_pos + 1))?;
+                    match parse_class_escape(chars, j, e, case_insensitive)? {
+                        ClassAtom::Char(c) => c,
+                        ClassAtom::Class(_) => {
+                            return Err(ParserError::InvalidEscape(escape_span(j, e), e))
+                        }
+                    }
+                }
+                Some((_, c)) => c,
+                None => return Err(ParserError::NoRightBracket(open_pos..open_pos + 1)),
+            }
+        } else {
+            start
+        };
+
+        if end < start {
+            return Err(ParserError::InvalidRange(i..chars.offset(), start, end));
+        }
+        operand = operand.union(&new_class(&[(start, end)], false, case_insensitive));
+    }
+
+    Ok(if negated { class.negate() } else { class })
+}
+
+/// Reads a decimal number, returning `Some(None)` if there are no digits and `None` on overflow.
+fn parse_number(chars: &mut ExprChars) -> Option<Option<usize>> {
+    let mut n: Option<usize> = None;
+
+    while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+        let digit = c.to_digit(10).unwrap() as usize;
+        n = Some(n.unwrap_or(0).checked_mul(10)?.checked_add(digit)?);
+    }
+
+    Some(n)
+}
+
+/// Parses `{n}`, `{n,}` or `{n,m}`. `open_pos` is the position of `{`, which `chars` has consumed.
+fn parse_repeat(chars: &mut ExprChars, open_pos: usize) -> Result<PSQ, ParserError> {
+    let min = parse_number(chars)
+        .flatten()
+        .ok_or_else(|| ParserError::InvalidRepeat(open_pos..chars.offset()))?;
+    let max = match chars.next() {
+        Some((_, '}')) => return Ok(PSQ::Repeat(min, Some(min))),
+        Some((_, ',')) => parse_number(chars)
+            .ok_or_else(|| ParserError::InvalidRepeat(open_pos..chars.offset()))?,
+        _ => return Err(ParserError::InvalidRepeat(open_pos..chars.offset())),
+    };
+
+    if !matches!(chars.next(), Some((_, '}'))) {
+        return Err(ParserError::InvalidRepeat(open_pos..chars.offset()));
+    }
+
+    match max {
+        Some(max) if max < min => Err(ParserError::InvalidRepeatRange(
+            open_pos..chars.offset(),
+            min,
+            max,
+        )),
+        _ => Ok(PSQ::Repeat(min, max)),
     }
 }
 
@@ -48,24 +869,111 @@ enum PSQ {
     Plus,
     Star,
     Question,
+    Repeat(usize, Option<usize>),
 }
 
 fn parse_plus_star_question(
     seq: &mut Vec<AST>,
     ast_type: PSQ,
-    pos: usize,
+    greedy: bool,
+    span: Range<usize>,
 ) -> Result<(), ParserError> {
     if let Some(prev) = seq.pop() {
         let ast = match ast_type {
-            PSQ::Plus => AST::Plus(Box::new(prev)),
-            PSQ::Star => AST::Star(Box::new(prev)),
-            PSQ::Question => AST::Question(Box::new(prev)),
+            PSQ::Plus => AST::Plus(Box::new(prev), greedy),
+            PSQ::Star => AST::Star(Box::new(prev), greedy),
+            PSQ::Question => AST::Question(Box::new(prev), greedy),
+            PSQ::Repeat(min, max) => AST::Repeat {
+                ast: Box::new(prev),
+                min,
+                max,
+                greedy,
+            },
         };
 
         seq.push(ast);
         Ok(())
     } else {
-        Err(ParserError::NoPrev(pos))
+        Err(ParserError::NoPrev(span))
+    }
+}
+
+/// Parses the name of `(?P<name>` or `(?<name>`, whose `(?` `chars` has consumed, or returns
+/// `None` if the group is not named. `open_pos` is the position of `(`.
+/// A name is a letter or `_` followed by letters, digits or `_`.
+fn parse_group_name(chars: &mut ExprChars, open_pos: usize) -> Result<Option<String>, ParserError> {
+    let mut ahead = chars.clone();
+    let is_named = match ahead.next() {
+        Some((_, 'P')) => matches!(ahead.next(), Some((_, '<'))),
+        Some((_, '<')) => true,
+        _ => false,
+    };
+    if !is_named {
+        return Ok(None);
+    }
+    chars.next_if(|(_, c)| *c == 'P');
+    chars.next();
+
+    let mut name = String::new();
+    loop {
+        match chars.next() {
+            Some((_, '>')) if !name.is_empty() => return Ok(Some(name)),
+            Some((_, c)) if c.is_ascii_alphabetic() || c == '_' => name.push(c),
+            Some((_, c)) if c.is_ascii_digit() && !name.is_empty() => name.push(c),
+            _ => return Err(ParserError::InvalidGroupName(open_pos..chars.offset())),
+        }
+    }
+}
+
+/// The most chars that up to `max` repetitions of `ast` can match, where `None` is unlimited.
+fn repeat_bound(ast: &AST, max: Option<usize>) -> Option<usize> {
+    match (ast.len_bounds().1, max) {
+        (Some(0), _) => Some(0),
+        (Some(len), Some(max)) => len.checked_mul(max),
+        _ => None,
+    }
+}
+
+/// What a `(` opened, to build once its `)` is reached.
+enum Group {
+    Capture(usize, Option<String>),
+    NonCapture,
+    Lookahead(bool),
+    Lookbehind(bool),
+}
+
+/// Parses the start of `(?=`, `(?!`, `(?<=` or `(?<!`, whose `(?` `chars` has consumed, or
+/// returns `None` for other groups.
+fn parse_look(chars: &mut ExprChars) -> Option<Group> {
+    let mut ahead = chars.clone();
+    let group = match ahead.next()? {
+        (_, '=') => Group::Lookahead(true),
+        (_, '!') => Group::Lookahead(false),
+        (_, '<') => match ahead.next()? {
+            (_, '=') => Group::Lookbehind(true),
+            (_, '!') => Group::Lookbehind(false),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    *chars = ahead;
+    Some(group)
+}
+
+/// Makes a char or a class also match the case variants of its chars when `i` is set.
+fn apply_case(ast: AST, flags: Flags) -> AST {
+    if !flags.case_insensitive {
+        return ast;
+    }
+
+    match ast {
+        AST::Char(c) if !case_variants(c).is_empty() => {
+            let mut ranges = vec![(c, c)];
+            ranges.extend(case_variants(c).iter().map(|v| (*v, *v)));
+            AST::Class(CharClass::new(ranges, false))
+        }
+        ast => ast,
     }
 }
 
@@ -85,6 +993,11 @@ fn fold_or(mut seq_or: Vec<AST>) -> Option<AST> {
 }
 
 pub fn parse(expr: &str) -> Result<AST, ParserError> {
+    parse_with_flags(expr, Flags::default())
+}
+
+/// Parses `expr` with `flags` set from the start, as the builder options do.
+pub fn parse_with_flags(expr: &str, mut flags: Flags) -> Result<AST, ParserError> {
     enum ParseState {
         Char,
         Escape,
@@ -96,88 +1009,169 @@ pub fn parse(expr: &str) -> Result<AST, ParserError> {
         seq_or: Vec<AST>,
     }
 
-    let mut is_hat = false;
-    let mut is_dollar = false;
+    let mut next_group = 1;
+    let mut names = HashMap::new();
     let mut context = Context::default();
     let mut stack = vec![];
     let mut state = ParseState::Char;
 
-    for (i, c) in expr.chars().enumerate() {
+    let mut chars = ExprChars::new(expr);
+
+    while let Some((i, c)) = chars.next() {
         match state {
             ParseState::Char => match c {
-                '+' => parse_plus_star_question(&mut context.seq_quantifier, PSQ::Plus, i)?,
-                '*' => parse_plus_star_question(&mut context.seq_quantifier, PSQ::Star, i)?,
-                '?' => parse_plus_star_question(&mut context.seq_quantifier, PSQ::Question, i)?,
+                '+' | '*' | '?' | '{' => {
+                    let ast_type = match c {
+                        '+' => PSQ::Plus,
+                        '*' => PSQ::Star,
+                        '?' => PSQ::Question,
+                        _ => parse_repeat(&mut chars, i)?,
+                    };
+                    let greedy = chars.next_if(|(_, c)| *c == '?').is_none();
+                    let span = i..chars.offset();
+                    parse_plus_star_question(&mut context.seq_quantifier, ast_type, greedy, span)?
+                }
                 '(' => {
+                    if chars.next_if(|(_, c)| *c == '?').is_some() {
+                        if let Some(group) = parse_look(&mut chars) {
+                            let prev = take(&mut context);
+                            stack.push((prev, group, flags, i));
+                            continue;
+                        }
+
+                        if let Some(name) = parse_group_name(&mut chars, i)? {
+                            if names.insert(name.clone(), next_group).is_some() {
+                                let span = i..chars.offset();
+                                return Err(ParserError::DuplicateGroupName(span, name));
+                            }
+
+                            let prev = take(&mut context);
+                            stack.push((prev, Group::Capture(next_group, Some(name)), flags, i));
+                            next_group += 1;
+                            continue;
+                        }
+
+                        let (group_flags, scoped) = parse_flags(&mut chars, flags, i)?;
+                        if scoped {
+                            let prev = take(&mut context);
+                            stack.push((prev, Group::NonCapture, flags, i));
+                        }
+                        flags = group_flags;
+                        continue;
+                    }
+
                     let prev = take(&mut context);
-                    stack.push(prev);
+                    stack.push((prev, Group::Capture(next_group, None), flags, i));
+                    next_group += 1;
                 }
                 ')' => {
-                    if let Some(mut prev) = stack.pop() {
-                        if !context.seq_quantifier.is_empty() {
-                            context.seq_or.push(AST::Seq(context.seq_quantifier))
-                        }
+                    let (mut prev, group, prev_flags, open_pos) = stack
+                        .pop()
+                        .ok_or(ParserError::UnmatchedRightParen(i..i + 1))?;
+                    // A last alternative left empty, as in `(a|)`, matches the empty string.
+                    if !context.seq_quantifier.is_empty() || !context.seq_or.is_empty() {
+                        context.seq_or.push(AST::Seq(context.seq_quantifier))
+                    }
 
-                        if let Some(ast) = fold_or(context.seq_or) {
-                            prev.seq_quantifier.push(ast);
+                    let ast = fold_or(context.seq_or).unwrap_or(AST::Seq(vec![]));
+                    prev.seq_quantifier.push(match group {
+                        Group::Capture(index, name) => AST::Capture(index, name, Box::new(ast)),
+                        Group::NonCapture => ast,
+                        Group::Lookahead(positive) => AST::Lookahead(Box::new(ast), positive),
+                        Group::Lookbehind(positive) => {
+                            if ast.len_bounds().1.is_none() {
+                                return Err(ParserError::UnboundedLookbehind(open_pos..i + 1));
+                            }
+                            AST::Lookbehind(Box::new(ast), positive)
                         }
+                    });
 
-                        context = prev;
-                    }
+                    context = prev;
+                    flags = prev_flags;
                 }
                 '|' => {
                     if context.seq_quantifier.is_empty() {
-                        return Err(ParserError::NoPrev(i));
+                        return Err(ParserError::NoPrev(i..i + 1));
                     }
                     let prev_quantifier = take(&mut context.seq_quantifier);
                     context.seq_or.push(AST::Seq(prev_quantifier));
                 }
-                '.' => context.seq_quantifier.push(AST::AnyChar),
-                '^' => {
-                    if i > 0 {
-                        return Err(ParserError::InvalidHat);
-                    } else {
-                        is_hat = true;
-                    }
-                }
-                '$' => {
-                    if i < expr.len() - 1 {
-                        return Err(ParserError::InvalidDollar);
-                    } else {
-                        is_dollar = true;
-                    }
+                '.' if flags.dot_all => context.seq_quantifier.push(AST::AnyChar),
+                '.' => context.seq_quantifier.push(AST::AnyCharExceptNewline),
+                '[' => {
+                    let class = parse_class(&mut chars, i, flags.case_insensitive)?;
+                    context.seq_quantifier.push(AST::Class(class));
                 }
+                '^' if flags.multi_line => context.seq_quantifier.push(AST::LineStart(flags.crlf)),
+                '$' if flags.multi_line => context.seq_quantifier.push(AST::LineEnd(flags.crlf)),
+                '^' => context.seq_quantifier.push(AST::Hat),
+                '$' => context.seq_quantifier.push(AST::Dollar),
                 '\\' => {
                     state = ParseState::Escape;
                 }
-                _ => context.seq_quantifier.push(AST::Char(c)),
+                _ => context.seq_quantifier.push(apply_case(AST::Char(c), flags)),
             },
             _ => {
-                let ast = parse_escape(i, c)?;
-                context.seq_quantifier.push(ast);
+                let ast = match c {
+                    '1'..='9' => {
+                        let mut group = c.to_digit(10).unwrap() as usize;
+                        while let Some((_, d)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                            let digit = d.to_digit(10).unwrap() as usize;
+                            group = group
+                                .checked_mul(10)
+                                .and_then(|g| g.checked_add(digit))
+                                .ok_or_else(|| {
+                                    ParserError::InvalidBackref(i - 1..chars.offset())
+                                })?;
+                        }
+                        if group >= next_group {
+                            return Err(ParserError::InvalidBackref(i - 1..chars.offset()));
+                        }
+                        AST::Backref(group, flags.case_insensitive)
+                    }
+                    'k' => {
+                        // `P<` opens a named group but not a backreference.
+                        let name = match chars.clone().next() {
+                            Some((_, '<')) => parse_group_name(&mut chars, i - 1)?,
+                            _ => None,
+                        }
+                        .ok_or(ParserError::InvalidEscape(escape_span(i, c), c))?;
+                        let group = names
+                            .get(&name)
+                            .ok_or_else(|| ParserError::InvalidBackref(i - 1..chars.offset()))?;
+                        AST::Backref(*group, flags.case_insensitive)
+                    }
+                    'p' | 'P' => {
+                        let ranges = parse_property(&mut chars, i)?;
+                        AST::Class(new_class(&ranges, c == 'P', flags.case_insensitive))
+                    }
+                    _ => match parse_char_escape(&mut chars, i, c)? {
+                        Some(c) => AST::Char(c),
+                        None => parse_escape(i, c, flags.case_insensitive)?,
+                    },
+                };
+                context.seq_quantifier.push(apply_case(ast, flags));
                 state = ParseState::Char;
             }
         }
     }
 
-    if !stack.is_empty() {
-        return Err(ParserError::NoRightParen);
+    if let ParseState::Escape = state {
+        return Err(ParserError::TrailingEscape(expr.len() - 1..expr.len()));
+    }
+
+    if let Some((.., open_pos)) = stack.last() {
+        return Err(ParserError::NoRightParen(*open_pos..open_pos + 1));
     }
 
-    if !context.seq_quantifier.is_empty() {
+    if !context.seq_quantifier.is_empty() || !context.seq_or.is_empty() {
         context.seq_or.push(AST::Seq(context.seq_quantifier));
     }
 
     if let Some(ast) = fold_or(context.seq_or) {
-        let ast = if is_hat { AST::Hat(Box::new(ast)) } else { ast };
-        let ast = if is_dollar {
-            AST::Dollar(Box::new(ast))
-        } else {
-            ast
-        };
         Ok(ast)
     } else {
-        Err(ParserError::Empty)
+        Err(ParserError::Empty(0..expr.len()))
     }
 }
 
@@ -217,20 +1211,787 @@ mod tests {
     }
 
     #[test]
-    fn hat_dollar_case() {
-        let expr = "^a";
+    fn capture_case() {
+        assert_eq!(
+            parse("(a(b))|()").unwrap(),
+            AST::Or(
+                Box::new(AST::Seq(vec![AST::Capture(
+                    1,
+                    None,
+                    Box::new(AST::Seq(vec![
+                        AST::Char('a'),
+                        AST::Capture(2, None, Box::new(AST::Seq(vec![AST::Char('b')])))
+                    ]))
+                )])),
+                Box::new(AST::Seq(vec![AST::Capture(
+                    3,
+                    None,
+                    Box::new(AST::Seq(vec![]))
+                )]))
+            )
+        );
+        assert_eq!(parse("(a(b))|()").unwrap().captures_len(), 4);
+        assert_eq!(parse("ab").unwrap().captures_len(), 1);
+    }
 
+    #[test]
+    fn class_case() {
+        assert_eq!(
+            parse("[a-z0_]").unwrap(),
+            AST::Seq(vec![AST::Class(CharClass::new(
+                vec![('a', 'z'), ('0', '0'), ('_', '_')],
+                false
+            ))])
+        );
         assert_eq!(
-            parse("^a").unwrap(),
-            AST::Hat(Box::new(AST::Seq(vec![AST::Char('a')]))),
+            parse("[^]a-]").unwrap(),
+            AST::Seq(vec![AST::Class(CharClass::new(
+                vec![(']', ']'), ('a', 'a'), ('-', '-')],
+                true
+            ))])
         );
         assert_eq!(
-            parse("a$").unwrap(),
-            AST::Dollar(Box::new(AST::Seq(vec![AST::Char('a')]))),
+            parse(r"[\]\-\\]").unwrap(),
+            AST::Seq(vec![AST::Class(CharClass::new(
+                vec![(']', ']'), ('-', '-'), ('\\', '\\')],
+                false
+            ))])
+        );
+    }
+
+    #[test]
+    fn class_error_case() {
+        assert!(matches!(
+            parse("ab[cd"),
+            Err(ParserError::NoRightBracket(Range { start: 2, .. }))
+        ));
+        assert!(matches!(
+            parse("a[^"),
+            Err(ParserError::NoRightBracket(Range { start: 1, .. }))
+        ));
+        assert!(matches!(
+            parse("[a-"),
+            Err(ParserError::NoRightBracket(Range { start: 0, .. }))
+        ));
+        assert!(matches!(
+            parse("x[az-a]"),
+            Err(ParserError::InvalidRange(Range { start: 3, .. }, 'z', 'a'))
+        ));
+    }
+
+    #[test]
+    fn perl_class_case() {
+        assert_eq!(
+            parse(r"\d\W\b\B").unwrap(),
+            AST::Seq(vec![
+                AST::Class(CharClass::new(vec![('0', '9')], false)),
+                AST::Class(CharClass::new(WORD_RANGES.to_vec(), true)),
+                AST::WordBoundary,
+                AST::NotWordBoundary,
+            ])
         );
+        assert_eq!(
+            parse(r"[\s_]").unwrap(),
+            AST::Seq(vec![AST::Class(CharClass::new(
+                vec![('\t', '\r'), (' ', ' '), ('_', '_')],
+                false
+            ))])
+        );
+        assert_eq!(
+            parse(r"[^\D]").unwrap(),
+            AST::Seq(vec![AST::Class(CharClass::new(
+                vec![('\0', '/'), (':', char::MAX)],
+                true
+            ))])
+        );
+        assert!(matches!(
+            parse(r"\q"),
+            Err(ParserError::InvalidEscape(Range { start: 0, end: 2 }, 'q'))
+        ));
+        assert!(matches!(
+            parse(r"[a-\d]"),
+            Err(ParserError::InvalidEscape(Range { start: 3, end: 5 }, 'd'))
+        ));
+    }
+
+    #[test]
+    fn repeat_case() {
+        let repeat = |min, max| AST::Repeat {
+            ast: Box::new(AST::Char('a')),
+            min,
+            max,
+            greedy: true,
+        };
+
+        assert_eq!(parse("a{3}").unwrap(), AST::Seq(vec![repeat(3, Some(3))]));
+        assert_eq!(parse("a{3,}").unwrap(), AST::Seq(vec![repeat(3, None)]));
+        assert_eq!(
+            parse("a{0,12}").unwrap(),
+            AST::Seq(vec![repeat(0, Some(12))])
+        );
+        assert_eq!(
+            parse(r"\{}").unwrap(),
+            AST::Seq(vec![AST::Char('{'), AST::Char('}')])
+        );
+    }
+
+    #[test]
+    fn lazy_case() {
+        assert_eq!(
+            parse("a*?b+c??d{1,2}?").unwrap(),
+            AST::Seq(vec![
+                AST::Star(Box::new(AST::Char('a')), false),
+                AST::Plus(Box::new(AST::Char('b')), true),
+                AST::Question(Box::new(AST::Char('c')), false),
+                AST::Repeat {
+                    ast: Box::new(AST::Char('d')),
+                    min: 1,
+                    max: Some(2),
+                    greedy: false
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn repeat_error_case() {
+        assert!(matches!(
+            parse("{2}"),
+            Err(ParserError::NoPrev(Range { start: 0, .. }))
+        ));
+        assert!(matches!(
+            parse("a{"),
+            Err(ParserError::InvalidRepeat(Range { start: 1, .. }))
+        ));
+        assert!(matches!(
+            parse("ab{,2}"),
+            Err(ParserError::InvalidRepeat(Range { start: 2, .. }))
+        ));
+        assert!(matches!(
+            parse("a{2,x}"),
+            Err(ParserError::InvalidRepeat(Range { start: 1, .. }))
+        ));
+        assert!(matches!(
+            parse("a{2"),
+            Err(ParserError::InvalidRepeat(Range { start: 1, .. }))
+        ));
+        assert!(matches!(
+            parse("a{99999999999999999999}"),
+            Err(ParserError::InvalidRepeat(Range { start: 1, .. }))
+        ));
+        assert!(matches!(
+            parse("a{3,2}"),
+            Err(ParserError::InvalidRepeatRange(
+                Range { start: 1, .. },
+                3,
+                2
+            ))
+        ));
+    }
+
+    #[test]
+    fn hat_dollar_case() {
         assert_eq!(
             parse("^a$").unwrap(),
-            AST::Dollar(Box::new(AST::Hat(Box::new(AST::Seq(vec![AST::Char('a')]))))),
+            AST::Seq(vec![AST::Hat, AST::Char('a'), AST::Dollar]),
+        );
+        assert_eq!(
+            parse("(^a|b$)").unwrap(),
+            AST::Seq(vec![AST::Capture(
+                1,
+                None,
+                Box::new(AST::Or(
+                    Box::new(AST::Seq(vec![AST::Hat, AST::Char('a')])),
+                    Box::new(AST::Seq(vec![AST::Char('b'), AST::Dollar]))
+                ))
+            )]),
+        );
+        assert_eq!(
+            parse("x|^y").unwrap(),
+            AST::Or(
+                Box::new(AST::Seq(vec![AST::Char('x')])),
+                Box::new(AST::Seq(vec![AST::Hat, AST::Char('y')]))
+            ),
+        );
+        assert_eq!(parse("$^").unwrap(), AST::Seq(vec![AST::Dollar, AST::Hat]));
+    }
+
+    #[test]
+    fn multi_line_case() {
+        assert_eq!(
+            parse("(?m)^a$").unwrap(),
+            AST::Seq(vec![
+                AST::LineStart(false),
+                AST::Char('a'),
+                AST::LineEnd(false)
+            ])
+        );
+        assert_eq!(
+            parse("(?mR)a$|(?-m)b").unwrap(),
+            AST::Or(
+                Box::new(AST::Seq(vec![AST::Char('a'), AST::LineEnd(true)])),
+                Box::new(AST::Seq(vec![AST::Char('b')]))
+            )
+        );
+        assert_eq!(
+            parse("((?m)^)^").unwrap(),
+            AST::Seq(vec![
+                AST::Capture(1, None, Box::new(AST::Seq(vec![AST::LineStart(false)]))),
+                AST::Hat
+            ])
+        );
+        assert_eq!(
+            parse_with_flags(
+                "a$",
+                Flags {
+                    multi_line: true,
+                    ..Flags::default()
+                }
+            )
+            .unwrap(),
+            AST::Seq(vec![AST::Char('a'), AST::LineEnd(false)])
+        );
+        assert_eq!(
+            parse(r"\Aa\z").unwrap(),
+            AST::Seq(vec![AST::Hat, AST::Char('a'), AST::Dollar])
+        );
+        assert!(matches!(
+            parse("(?x)a"),
+            Err(ParserError::InvalidFlag(Range { start: 2, .. }, 'x'))
+        ));
+        assert!(matches!(parse("(?m"), Err(ParserError::NoRightParen(_))));
+        assert!(matches!(
+            parse("(?)a"),
+            Err(ParserError::MissingFlag(Range { start: 2, end: 3 }))
+        ));
+        assert!(matches!(
+            parse("(?-)a"),
+            Err(ParserError::MissingFlag(Range { start: 3, end: 4 }))
+        ));
+        assert!(matches!(
+            parse("(?-:a)"),
+            Err(ParserError::MissingFlag(Range { start: 3, end: 4 }))
+        ));
+        assert!(matches!(
+            parse("(?i-)a"),
+            Err(ParserError::MissingFlag(Range { start: 4, end: 5 }))
+        ));
+        assert!(parse("(?-i)a").is_ok());
+    }
+
+    #[test]
+    fn case_insensitive_case() {
+        let class = |ranges: &[(char, char)]| AST::Class(CharClass::new(ranges.to_vec(), false));
+
+        assert_eq!(
+            parse("(?i)a1").unwrap(),
+            AST::Seq(vec![class(&[('a', 'a'), ('A', 'A')]), AST::Char('1')])
+        );
+        assert_eq!(
+            parse("a(?i:k)b").unwrap(),
+            AST::Seq(vec![
+                AST::Char('a'),
+                AST::Seq(vec![class(&[
+                    ('k', 'k'),
+                    ('K', 'K'),
+                    ('\u{212A}', '\u{212A}')
+                ])]),
+                AST::Char('b')
+            ])
+        );
+        assert_eq!(
+            parse("((?i)x)x").unwrap(),
+            AST::Seq(vec![
+                AST::Capture(
+                    1,
+                    None,
+                    Box::new(AST::Seq(vec![class(&[('x', 'x'), ('X', 'X')])]))
+                ),
+                AST::Char('x')
+            ])
+        );
+        assert_eq!(
+            parse("(?i)[^a-b]").unwrap(),
+            AST::Seq(vec![AST::Class(CharClass::new(
+                vec![('a', 'b'), ('A', 'A'), ('B', 'B')],
+                true
+            ))])
+        );
+        assert_eq!(
+            parse("(?i:(?-i)a)").unwrap(),
+            AST::Seq(vec![AST::Seq(vec![AST::Char('a')])])
+        );
+        assert!(matches!(parse("(?i:a"), Err(ParserError::NoRightParen(_))));
+    }
+
+    #[test]
+    fn group_case() {
+        assert_eq!(
+            parse("(?:ab)+(c)").unwrap(),
+            AST::Seq(vec![
+                AST::Plus(
+                    Box::new(AST::Seq(vec![AST::Char('a'), AST::Char('b')])),
+                    true
+                ),
+                AST::Capture(1, None, Box::new(AST::Seq(vec![AST::Char('c')]))),
+            ])
+        );
+        assert_eq!(
+            parse("(?P<first>a)(?<_2nd>b)").unwrap(),
+            AST::Seq(vec![
+                AST::Capture(
+                    1,
+                    Some("first".to_string()),
+                    Box::new(AST::Seq(vec![AST::Char('a')]))
+                ),
+                AST::Capture(
+                    2,
+                    Some("_2nd".to_string()),
+                    Box::new(AST::Seq(vec![AST::Char('b')]))
+                ),
+            ])
+        );
+        assert_eq!(
+            parse("(a)(?:(?<x>b)|c)(d)").unwrap().capture_names(),
+            vec![None, None, Some("x".to_string()), None]
+        );
+    }
+
+    #[test]
+    fn group_error_case() {
+        assert!(matches!(
+            parse("a(?P<x>b)(?<x>c)"),
+            Err(ParserError::DuplicateGroupName(Range { start: 9, .. }, name)) if name == "x"
+        ));
+        assert!(matches!(
+            parse("(?<>a)"),
+            Err(ParserError::InvalidGroupName(Range { start: 0, .. }))
+        ));
+        assert!(matches!(
+            parse("b(?<1x>a)"),
+            Err(ParserError::InvalidGroupName(Range { start: 1, .. }))
+        ));
+        assert!(matches!(
+            parse("(?P<a-b>a)"),
+            Err(ParserError::InvalidGroupName(Range { start: 0, .. }))
+        ));
+        assert!(matches!(
+            parse("(?<ab"),
+            Err(ParserError::InvalidGroupName(Range { start: 0, .. }))
+        ));
+        assert!(matches!(
+            parse("(?P=a)"),
+            Err(ParserError::InvalidFlag(Range { start: 2, .. }, 'P'))
+        ));
+    }
+
+    #[test]
+    fn backref_case() {
+        assert_eq!(
+            parse(r"(a)\1").unwrap(),
+            AST::Seq(vec![
+                AST::Capture(1, None, Box::new(AST::Seq(vec![AST::Char('a')]))),
+                AST::Backref(1, false)
+            ])
+        );
+        assert_eq!(
+            parse(r"(?<x>a)(b)\k<x>\2").unwrap(),
+            AST::Seq(vec![
+                AST::Capture(
+                    1,
+                    Some("x".to_string()),
+                    Box::new(AST::Seq(vec![AST::Char('a')]))
+                ),
+                AST::Capture(2, None, Box::new(AST::Seq(vec![AST::Char('b')]))),
+                AST::Backref(1, false),
+                AST::Backref(2, false)
+            ])
+        );
+        assert_eq!(
+            parse(r"(a)(?i)\1").unwrap(),
+            AST::Seq(vec![
+                AST::Capture(1, None, Box::new(AST::Seq(vec![AST::Char('a')]))),
+                AST::Backref(1, true)
+            ])
+        );
+        assert_eq!(
+            parse(r"(((((((((((a)))))))))))\11").unwrap().captures_len(),
+            12
+        );
+        assert!(matches!(
+            parse(r"\1(a)"),
+            Err(ParserError::InvalidBackref(Range { start: 0, end: 2 }))
+        ));
+        assert!(matches!(
+            parse(r"(a)\10"),
+            Err(ParserError::InvalidBackref(Range { start: 3, end: 6 }))
+        ));
+        assert!(matches!(
+            parse(r"(?<x>a)\k<y>"),
+            Err(ParserError::InvalidBackref(Range { start: 7, end: 12 }))
+        ));
+        assert!(matches!(
+            parse(r"\k"),
+            Err(ParserError::InvalidEscape(Range { start: 0, end: 2 }, 'k'))
+        ));
+        assert!(matches!(
+            parse(r"(?<x>a)\kP<x>"),
+            Err(ParserError::InvalidEscape(Range { start: 7, end: 9 }, 'k'))
+        ));
+    }
+
+    #[test]
+    fn lookaround_case() {
+        assert_eq!(
+            parse("(?=a)b(?!c)").unwrap(),
+            AST::Seq(vec![
+                AST::Lookahead(Box::new(AST::Seq(vec![AST::Char('a')])), true),
+                AST::Char('b'),
+                AST::Lookahead(Box::new(AST::Seq(vec![AST::Char('c')])), false),
+            ])
+        );
+        assert_eq!(
+            parse("(?<!ab|c)(?<=(x))").unwrap(),
+            AST::Seq(vec![
+                AST::Lookbehind(
+                    Box::new(AST::Or(
+                        Box::new(AST::Seq(vec![AST::Char('a'), AST::Char('b')])),
+                        Box::new(AST::Seq(vec![AST::Char('c')]))
+                    )),
+                    false
+                ),
+                AST::Lookbehind(
+                    Box::new(AST::Seq(vec![AST::Capture(
+                        1,
+                        None,
+                        Box::new(AST::Seq(vec![AST::Char('x')]))
+                    )])),
+                    true
+                ),
+            ])
+        );
+        assert!(parse("(?<=a{1,3}b?)").is_ok());
+        assert!(matches!(
+            parse("x(?<=a+)"),
+            Err(ParserError::UnboundedLookbehind(Range { start: 1, .. }))
+        ));
+        assert!(matches!(
+            parse(r"(a)(?<!\1)"),
+            Err(ParserError::UnboundedLookbehind(Range { start: 3, .. }))
+        ));
+        assert!(matches!(parse("(?=a"), Err(ParserError::NoRightParen(_))));
+    }
+
+    #[test]
+    fn len_bounds_case() {
+        let bounds = |expr| parse(expr).unwrap().len_bounds();
+
+        assert_eq!(bounds("ab"), (2, Some(2)));
+        assert_eq!(bounds("a|bcd"), (1, Some(3)));
+        assert_eq!(bounds("(ab){2,3}c?"), (4, Some(7)));
+        assert_eq!(bounds("a+"), (1, None));
+        assert_eq!(bounds("^(?=x*)$"), (0, Some(0)));
+        assert_eq!(bounds("(\\b)*"), (0, Some(0)));
+    }
+
+    #[test]
+    fn char_escape_case() {
+        assert_eq!(
+            parse(r"\t\n\r\x41\x{1F600}\u00e9\u{10FFFF}").unwrap(),
+            AST::Seq(vec![
+                AST::Char('\t'),
+                AST::Char('\n'),
+                AST::Char('\r'),
+                AST::Char('A'),
+                AST::Char('😀'),
+                AST::Char('é'),
+                AST::Char('\u{10FFFF}'),
+            ])
+        );
+        assert_eq!(
+            parse(r"[\t\x00-\x1F]").unwrap(),
+            AST::Seq(vec![AST::Class(CharClass::new(
+                vec![('\t', '\t'), ('\0', '\x1F')],
+                false
+            ))])
+        );
+
+        assert!(matches!(
+            parse(r"a\x4"),
+            Err(ParserError::InvalidHex(Range { start: 1, end: 4 }))
+        ));
+        assert!(matches!(
+            parse(r"\xg1"),
+            Err(ParserError::InvalidHex(Range { start: 0, end: 3 }))
+        ));
+        assert!(matches!(
+            parse(r"\x{}"),
+            Err(ParserError::InvalidHex(Range { start: 0, end: 4 }))
+        ));
+        assert!(matches!(
+            parse(r"\u{41"),
+            Err(ParserError::InvalidHex(Range { start: 0, end: 5 }))
+        ));
+        assert!(matches!(
+            parse(r"[\u12]"),
+            Err(ParserError::InvalidHex(Range { start: 1, end: 6 }))
+        ));
+        assert!(matches!(
+            parse(r"\x{110000}"),
+            Err(ParserError::InvalidCodePoint(
+                Range { start: 0, end: 10 },
+                0x110000
+            ))
+        ));
+        assert!(matches!(
+            parse(r"[a\u{D800}]"),
+            Err(ParserError::InvalidCodePoint(
+                Range { start: 2, end: 10 },
+                0xD800
+            ))
+        ));
+        assert!(matches!(
+            parse(r"\x{FFFFFFFFFF}"),
+            Err(ParserError::InvalidCodePoint(
+                Range { start: 0, end: 14 },
+                u32::MAX
+            ))
+        ));
+    }
+
+    #[test]
+    fn property_case() {
+        let class = |expr| match parse(expr).unwrap() {
+            AST::Seq(mut seq) => match seq.pop() {
+                Some(AST::Class(class)) => class,
+                ast => panic!("{:?}", ast),
+            },
+            ast => panic!("{:?}", ast),
+        };
+
+        assert!(class(r"\pL").contains('ж'));
+        assert!(!class(r"\PL").contains('ж'));
+        assert!(class(r"\p{Greek}").contains('λ'));
+        assert!(!class(r"\p{greek}").contains('l'));
+        assert!(class(r"\p{sc=Han}").contains('漢'));
+        assert!(class(r"\p{Script = Latin}").contains('é'));
+        assert!(class(r"\p{gc:Lu}").contains('Ä'));
+        assert!(!class(r"\p{Uppercase_Letter}").contains('ä'));
+        assert!(class(r"\p{White_Space}").contains('\u{3000}'));
+        assert!(class(r"\p{Alphabetic}").contains('あ'));
+        assert!(class(r"\p{Cf}").contains('\u{200B}'));
+        assert!(class(r"\p{Any}").contains(char::MAX));
+        assert!(!class(r"\p{ASCII}").contains('é'));
+        assert!(!class(r"\p{Assigned}").contains('\u{378}'));
+        assert!(class(r"[\d\p{Greek}]").contains('λ'));
+        assert!(!class(r"[\P{Greek}]").contains('λ'));
+        assert!(class(r"[^\P{Nd}]").contains('٣'));
+
+        assert!(matches!(
+            parse(r"a\p{Klingon}"),
+            Err(ParserError::UnknownProperty(Range { start: 1, end: 12 }, name)) if name == "Klingon"
+        ));
+        assert!(matches!(
+            parse(r"\p{sc=Lu}"),
+            Err(ParserError::UnknownProperty(Range { start: 0, end: 9 }, _))
+        ));
+        assert!(matches!(
+            parse(r"\p{L"),
+            Err(ParserError::InvalidProperty(Range { start: 0, end: 4 }))
+        ));
+        assert!(matches!(
+            parse(r"[\p{}]"),
+            Err(ParserError::InvalidProperty(Range { start: 1, end: 5 }))
+        ));
+        assert!(matches!(
+            parse(r"\p"),
+            Err(ParserError::InvalidProperty(Range { start: 0, end: 2 }))
+        ));
+    }
+
+    #[test]
+    fn posix_class_case() {
+        assert_eq!(
+            parse("[[:digit:]_[:upper:]]").unwrap(),
+            AST::Seq(vec![AST::Class(CharClass::new(
+                vec![('0', '9'), ('_', '_'), ('A', 'Z')],
+                false
+            ))])
+        );
+        assert_eq!(
+            parse("[^[:^blank:]]").unwrap(),
+            AST::Seq(vec![AST::Class(CharClass::new(
+                vec![('\0', '\x08'), ('\n', '\x1F'), ('!', char::MAX)],
+                true
+            ))])
+        );
+        assert_eq!(
+            parse("[[:a]]").unwrap(),
+            AST::Seq(vec![AST::Class(CharClass::new(
+                vec![(':', ':'), ('a', 'a')],
+                false
+            ))])
+        );
+        assert!(matches!(
+            parse("[[:a]"),
+            Err(ParserError::NoRightBracket(Range { start: 0, .. }))
+        ));
+
+        assert!(matches!(
+            parse("a[x[:alfa:]]"),
+            Err(ParserError::UnknownPosixClass(Range { start: 3, .. }, name)) if name == "alfa"
+        ));
+        assert!(matches!(
+            parse("[[:^Space:]]"),
+            Err(ParserError::UnknownPosixClass(Range { start: 1, .. }, name)) if name == "Space"
+        ));
+    }
+
+    #[test]
+    fn class_set_case() {
+        let class = |ranges: &[(char, char)]| {
+            AST::Seq(vec![AST::Class(CharClass::new(ranges.to_vec(), false))])
+        };
+
+        assert_eq!(
+            parse("[a-z&&[^aeiou]]").unwrap(),
+            class(&[('b', 'd'), ('f', 'h'), ('j', 'n'), ('p', 't'), ('v', 'z')])
+        );
+        assert_eq!(
+            parse(r"[\w--\d_]").unwrap(),
+            class(&[('A', 'Z'), ('a', 'z')])
+        );
+        assert_eq!(
+            parse("[a-c[x-z]]").unwrap(),
+            class(&[('a', 'c'), ('x', 'z')])
+        );
+        assert_eq!(parse("[^[^a-c]]").unwrap(), class(&[('a', 'c')]));
+        assert_eq!(parse("[a-z--c-x&&a-d]").unwrap(), class(&[('a', 'b')]));
+        assert_eq!(parse("[a&&b]").unwrap(), class(&[]));
+        assert_eq!(
+            parse("[-a&b-]").unwrap(),
+            class(&[('&', '&'), ('-', '-'), ('a', 'b')])
+        );
+        assert_eq!(parse(r"[\&\&a]").unwrap(), class(&[('&', '&'), ('a', 'a')]));
+        assert_eq!(
+            parse("(?i)[a-z--k]").unwrap(),
+            class(&[
+                ('A', 'J'),
+                ('L', 'Z'),
+                ('a', 'j'),
+                ('l', 'z'),
+                ('\u{17F}', '\u{17F}')
+            ])
+        );
+
+        assert!(matches!(
+            parse("[a[b]"),
+            Err(ParserError::NoRightBracket(Range { start: 0, .. }))
+        ));
+        assert!(matches!(
+            parse("[a&&[z-a]]"),
+            Err(ParserError::InvalidRange(Range { start: 5, .. }, 'z', 'a'))
+        ));
+    }
+
+    #[test]
+    fn error_span_case() {
+        let span = |expr| parse(expr).unwrap_err().span();
+
+        assert_eq!(span("a)b"), 1..2);
+        assert_eq!(span("(a))"), 3..4);
+        assert!(matches!(
+            parse("(a)b)"),
+            Err(ParserError::UnmatchedRightParen(_))
+        ));
+        assert_eq!(span("a(b(c)"), 1..2);
+        assert_eq!(span("((?i:b"), 1..2);
+        assert_eq!(span("(?i"), 0..1);
+        assert_eq!(span("é[abc"), 2..3);
+        assert_eq!(span("ab{3,1}c"), 2..7);
+        assert_eq!(span("a{2,x}"), 1..5);
+        assert_eq!(span("*a"), 0..1);
+        assert_eq!(span("a|+?"), 2..4);
+        assert_eq!(span("[é-a]"), 1..5);
+        assert_eq!(span("(?x)"), 2..3);
+        assert_eq!(span("(?<1a>)"), 0..4);
+        assert_eq!(span("(?<n>a)(?<n>b)"), 7..12);
+        assert_eq!(span("x(?<=a+)"), 1..8);
+        assert_eq!(span("[[:alfa:]]"), 1..9);
+        assert_eq!(span("(?i)"), 0..4);
+        assert_eq!(span(r"a\"), 1..2);
+        assert_eq!(span(r"(é\"), 3..4);
+        assert!(matches!(
+            parse(r"\"),
+            Err(ParserError::TrailingEscape(Range { start: 0, end: 1 }))
+        ));
+        assert!(matches!(
+            parse(r"a\\"),
+            Ok(AST::Seq(seq)) if seq == vec![AST::Char('a'), AST::Char('\\')]
+        ));
+    }
+
+    #[test]
+    fn render_case() {
+        assert_eq!(
+            parse("a(bc|d").unwrap_err().render("a(bc|d"),
+            "regex parse error: unclosed group\n    a(bc|d\n     ^ unclosed group opened here\nhelp: add a `)` to close it"
+        );
+        assert_eq!(
+            parse("é{3,1}").unwrap_err().render("é{3,1}"),
+            "regex parse error: invalid repetition range\n    é{3,1}\n     ^~~~~ minimum 3 is greater than maximum 1\nhelp: write it as `{1,3}`"
+        );
+        assert_eq!(
+            parse(r"\qa").unwrap_err().render(r"\qa"),
+            "regex parse error: invalid escape `\\q`\n    \\qa\n    ^~ unrecognized escape sequence"
+        );
+        assert_eq!(
+            parse(r"ab\").unwrap_err().render(r"ab\"),
+            "regex parse error: trailing escape\n    ab\\\n      ^ nothing follows this `\\`\nhelp: escape it as `\\\\` to match a literal `\\`"
+        );
+        assert_eq!(
+            parse("日本(").unwrap_err().render("日本("),
+            "regex parse error: unclosed group\n    日本(\n        ^ unclosed group opened here\nhelp: add a `)` to close it"
+        );
+        assert_eq!(
+            parse("\ta(b").unwrap_err().render("\ta(b"),
+            "regex parse error: unclosed group\n        a(b\n         ^ unclosed group opened here\nhelp: add a `)` to close it"
+        );
+        assert_eq!(
+            parse("e\u{301}{2,1}").unwrap_err().render("e\u{301}{2,1}"),
+            "regex parse error: invalid repetition range\n    e\u{301}{2,1}\n     ^~~~~ minimum 2 is greater than maximum 1\nhelp: write it as `{1,2}`"
+        );
+        assert_eq!(
+            parse("[[:Space:]]").unwrap_err().help().unwrap(),
+            "did you mean `[:space:]`?"
+        );
+        assert_eq!(parse("[[:foo:]]").unwrap_err().help(), None);
+    }
+
+    #[test]
+    fn empty_alternative_case() {
+        let a = || Box::new(AST::Seq(vec![AST::Char('a')]));
+        let empty = || Box::new(AST::Seq(vec![]));
+
+        assert_eq!(parse("a|").unwrap(), AST::Or(a(), empty()));
+        assert_eq!(
+            parse("(a|)b").unwrap(),
+            AST::Seq(vec![
+                AST::Capture(1, None, Box::new(AST::Or(a(), empty()))),
+                AST::Char('b'),
+            ])
         );
+        assert!(matches!(
+            parse("(?:|a)"),
+            Err(ParserError::NoPrev(Range { start: 3, end: 4 }))
+        ));
+        assert!(matches!(
+            parse("a||b"),
+            Err(ParserError::NoPrev(Range { start: 2, end: 3 }))
+        ));
+        assert!(matches!(
+            parse("|"),
+            Err(ParserError::NoPrev(Range { start: 0, end: 1 }))
+        ));
     }
 }
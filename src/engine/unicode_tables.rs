@@ -0,0 +1,26 @@
+//! Unicode data generated by `ucd-generate`, as bundled with `regex-syntax`.
+//! See `unicode_tables/LICENSE-UNICODE` for the terms of the data.
+
+#[rustfmt::skip]
+#[allow(clippy::redundant_static_lifetimes)]
+pub mod case_folding_simple;
+
+#[rustfmt::skip]
+#[allow(clippy::redundant_static_lifetimes)]
+pub mod general_category;
+
+#[rustfmt::skip]
+#[allow(clippy::redundant_static_lifetimes)]
+pub mod property_bool;
+
+#[rustfmt::skip]
+#[allow(clippy::redundant_static_lifetimes)]
+pub mod property_names;
+
+#[rustfmt::skip]
+#[allow(clippy::redundant_static_lifetimes)]
+pub mod property_values;
+
+#[rustfmt::skip]
+#[allow(clippy::redundant_static_lifetimes)]
+pub mod script;
@@ -1,3 +1,4 @@
+#[allow(dead_code)]
 pub(crate) trait SafeAdd: Sized {
     fn safe_add(&self, n: &Self) -> Option<Self>;
 }
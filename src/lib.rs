@@ -1,17 +1,23 @@
+pub use crate::engine::evaluator::MatchKind;
 use crate::engine::evaluator::{eval, eval_with_pattern};
 use crate::engine::{codegen, parser};
 use crate::helper::DynError;
 
+pub mod bytes;
 mod engine;
 mod helper;
+mod regex;
+mod replace;
+
+pub use crate::engine::parser::{ParserError, PatternError};
+pub use crate::regex::{CaptureMatches, Captures, Matches, Regex, RegexBuilder, Split, SplitN};
+pub use crate::replace::{NoExpand, Replacer};
 
 pub fn do_matching(expr: &str, line: &str, is_depth: bool) -> Result<bool, DynError> {
-    let ast = parser::parse(expr)?;
+    let ast = parser::parse(expr).map_err(|e| PatternError::new(expr, e))?;
     let code = codegen::get_code(&ast)?;
 
-    let line: Vec<char> = line.chars().collect();
-
-    Ok(eval(&code, &line, is_depth)?)
+    Ok(eval(&code, line, is_depth)?)
 }
 
 pub fn do_matching_with_pattern(
@@ -19,19 +25,18 @@ pub fn do_matching_with_pattern(
     line: &str,
     is_depth: bool,
 ) -> Result<Option<Vec<char>>, DynError> {
-    let ast = parser::parse(expr)?;
+    let ast = parser::parse(expr).map_err(|e| PatternError::new(expr, e))?;
     let code = codegen::get_code(&ast)?;
 
-    let line: Vec<char> = line.chars().collect();
+    let pattern_range = eval_with_pattern(&code, line, 0, is_depth, MatchKind::LeftmostFirst)?;
 
-    let pattern_range = eval_with_pattern(&code, &line, is_depth)?;
-
-    Ok(pattern_range.map(move |r| line[r].to_vec()))
+    Ok(pattern_range.map(|r| line[r].chars().collect()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::engine::evaluator::eval_with_captures;
     use rstest::rstest;
 
     #[rstest]
@@ -56,10 +61,15 @@ mod tests {
     #[case("abc?", "ab", true)]
     #[case("^abc", "abcdef", true)]
     #[case("def$", "abcdef", true)]
+    #[case("(^a|b$)", "cab", true)]
+    #[case("x|^y", "yz", true)]
+    #[case("a(^|b)c", "abc", true)]
+    #[case("a$|^$", "", true)]
+    #[case("(?s)a.b", "a\nb", true)]
     fn matched_case(
         #[case] expr: &str,
         #[case] line: &str,
-        #[case] expect: bool,
+        #[case] _expect: bool,
         #[values(true, false)] is_depth: bool,
     ) {
         assert!(do_matching(expr, line, is_depth).unwrap());
@@ -71,21 +81,120 @@ mod tests {
     #[case("abc?", "acd", true)]
     #[case("abc$", "abcdef", true)]
     #[case("^def", "abcdef", true)]
+    #[case("(^a|b$)", "cabc", true)]
+    #[case("x|^y", "zy", true)]
+    #[case("a^b", "ab", true)]
+    #[case("a$b", "ab", true)]
+    #[case("a.b", "a\nb", true)]
     fn unmatched_case(
         #[case] expr: &str,
         #[case] line: &str,
-        #[case] expect: bool,
+        #[case] _expect: bool,
         #[values(true, false)] is_depth: bool,
     ) {
         assert!(!do_matching(expr, line, is_depth).unwrap());
     }
 
+    #[rstest]
+    #[case("[0-9]+", "abc123", Some("123"))]
+    #[case("[a-c]+", "xxabcabd", Some("abcab"))]
+    #[case("[^a-c]+", "abcxyzab", Some("xyz"))]
+    #[case("[-+]?[0-9]", "x-1", Some("-1"))]
+    #[case("[]]", "a]b", Some("]"))]
+    #[case("[a-z]", "123", None)]
+    #[case("[^a]", "aaa", None)]
+    #[case("[ぁ-ん]+", "abcひらがなabc", Some("ひらがな"))]
+    fn class_case(
+        #[case] expr: &str,
+        #[case] line: &str,
+        #[case] expect: Option<&str>,
+        #[values(true, false)] is_depth: bool,
+    ) {
+        assert_eq!(
+            do_matching_with_pattern(expr, line, is_depth).unwrap(),
+            expect.map(|e| e.chars().collect())
+        );
+    }
+
+    #[rstest]
+    #[case(r"\d+", "abc123def", Some("123"))]
+    #[case(r"\D+", "123abc456", Some("abc"))]
+    #[case(r"\w+", "  foo_bar1 baz", Some("foo_bar1"))]
+    #[case(r"\W+", "foo, bar", Some(", "))]
+    #[case(r"\s+", "foo \t\nbar", Some(" \t\n"))]
+    #[case(r"\S+", "  foo ", Some("foo"))]
+    #[case(r"[\d\s]+", "ab1 2c", Some("1 2"))]
+    #[case(r"\bfoo\b", "afoo foo", Some("foo"))]
+    #[case(r"\bfoo\b", "afoo foob", None)]
+    #[case(r"\Boo\B", "foo fooo", Some("oo"))]
+    #[case(r"\b", "", None)]
+    #[case(r"\B", "", Some(""))]
+    fn perl_class_case(
+        #[case] expr: &str,
+        #[case] line: &str,
+        #[case] expect: Option<&str>,
+        #[values(true, false)] is_depth: bool,
+    ) {
+        assert_eq!(
+            do_matching_with_pattern(expr, line, is_depth).unwrap(),
+            expect.map(|e| e.chars().collect())
+        );
+    }
+
+    #[rstest]
+    #[case("a{3}", "aaaaa", Some("aaa"))]
+    #[case("a{3}", "aa", None)]
+    #[case("a{2,}", "baaaab", Some("aaaa"))]
+    #[case("a{2,3}", "aaaaa", Some("aaa"))]
+    #[case("(ab){1,2}c", "abababc", Some("ababc"))]
+    #[case(r"\d{3}-\d{4}", "tel 555-1234", Some("555-1234"))]
+    #[case("xa{0}y", "xy", Some("xy"))]
+    #[case("a{0,0}", "a", Some(""))]
+    fn repeat_case(
+        #[case] expr: &str,
+        #[case] line: &str,
+        #[case] expect: Option<&str>,
+        #[values(true, false)] is_depth: bool,
+    ) {
+        assert_eq!(
+            do_matching_with_pattern(expr, line, is_depth).unwrap(),
+            expect.map(|e| e.chars().collect())
+        );
+    }
+
+    #[rstest]
+    #[case("a+?", "aaa", Some("a"))]
+    #[case("a*?", "aaa", Some(""))]
+    #[case("a??b", "ab", Some("ab"))]
+    #[case("<.+?>", "<a><b>", Some("<a>"))]
+    #[case("<.+>", "<a><b>", Some("<a><b>"))]
+    #[case("a{2,4}?", "aaaa", Some("aa"))]
+    #[case("a{2,}?", "aaaa", Some("aa"))]
+    #[case("a*?b", "aaab", Some("aaab"))]
+    #[case("ab|abc", "abc", Some("ab"))]
+    #[case("a|ab|abc", "xabc", Some("a"))]
+    #[case("(a*)*b", "aaab", Some("aaab"))]
+    #[case("(a|b)*?c", "abbc", Some("abbc"))]
+    fn leftmost_first_case(
+        #[case] expr: &str,
+        #[case] line: &str,
+        #[case] expect: Option<&str>,
+        #[values(true, false)] is_depth: bool,
+    ) {
+        assert_eq!(
+            do_matching_with_pattern(expr, line, is_depth).unwrap(),
+            expect.map(|e| e.chars().collect())
+        );
+    }
+
     #[rstest]
     #[case("*b", "bbb", true)]
     #[case("+b", "bbb", true)]
     #[case("|b", "bbb", true)]
     #[case("?b", "bbb", true)]
-    fn parse_error_case(#[case] expr: &str, #[case] line: &str, #[case] expect: bool) {
+    #[case("[ab", "bbb", true)]
+    #[case("[b-a]", "bbb", true)]
+    fn parse_error_case(#[case] expr: &str, #[case] line: &str, #[case] _expect: bool) {
         assert!(do_matching(expr, line, true).is_err());
     }
 
@@ -126,9 +235,55 @@ mod tests {
     }
 
     #[test]
-    fn with_pattern() {
+    fn width_first_pathological_case() {
+        let n = 64;
+        let expr = format!("{}{}", "a?".repeat(n), "a".repeat(n));
+        let line = "a".repeat(n);
+        assert!(do_matching(&expr, &line, false).unwrap());
+        assert_eq!(
+            do_matching_with_pattern(&expr, &line, false).unwrap(),
+            Some(line.chars().collect())
+        );
+    }
+
+    #[test]
+    fn backref_only_depth_first() {
+        assert!(do_matching(r"(ab)\1", "xabab", true).unwrap());
+        assert!(!do_matching(r"(ab)\1", "xabba", true).unwrap());
+
+        let err = do_matching(r"(ab)\1", "xabab", false).unwrap_err();
         assert_eq!(
-            do_matching_with_pattern("ab.", "aabcabd", true).unwrap(),
+            err.to_string(),
+            "backreferences are only supported by the depth-first engine"
+        );
+    }
+
+    #[test]
+    fn lookaround_only_depth_first() {
+        assert!(do_matching("a(?=b)", "ab", true).unwrap());
+        assert!(!do_matching("a(?!b)", "ab", true).unwrap());
+
+        let err = do_matching("(?<=a)b", "ab", false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "lookaround is only supported by the depth-first engine"
+        );
+    }
+
+    #[test]
+    fn width_first_empty_loop() {
+        assert!(do_matching("(a*)*", "aaa", false).unwrap());
+        assert!(do_matching("(a*)*b", "aaab", false).unwrap());
+        assert_eq!(
+            do_matching_with_pattern("(a*)*b", "caab", false).unwrap(),
+            Some(vec!['a', 'a', 'b'])
+        );
+    }
+
+    #[rstest]
+    fn with_pattern(#[values(true, false)] is_depth: bool) {
+        assert_eq!(
+            do_matching_with_pattern("ab.", "aabcabd", is_depth).unwrap(),
             Some(vec!['a', 'b', 'c'])
         );
 
@@ -137,13 +292,129 @@ mod tests {
         let code = codegen::get_code(&ast).unwrap();
         println!("{}", code);
         assert_eq!(
-            do_matching_with_pattern("(ab)*", "cababc", true).unwrap(),
+            do_matching_with_pattern("(ab)*", "cababc", is_depth).unwrap(),
+            Some(vec![])
+        );
+        assert_eq!(
+            do_matching_with_pattern("(ab)+", "cababc", is_depth).unwrap(),
             Some(vec!['a', 'b', 'a', 'b'])
         );
 
         assert_eq!(
-            do_matching_with_pattern("(ab)*c?", "cababc", true).unwrap(),
+            do_matching_with_pattern("(ab)*c?", "cababc", is_depth).unwrap(),
+            Some(vec!['c'])
+        );
+        assert_eq!(
+            do_matching_with_pattern("(ab)*c", "xababc", is_depth).unwrap(),
             Some(vec!['a', 'b', 'a', 'b', 'c'])
         );
+
+        assert_eq!(
+            do_matching_with_pattern("b.+d", "ăbç😀d", is_depth).unwrap(),
+            Some(vec!['b', 'ç', '😀', 'd'])
+        );
+    }
+
+    #[rstest]
+    #[case("é+", "aééb", Some(1..5))]
+    #[case(r"\bb", "ébb b", Some(2..3))]
+    #[case("a.$", "xa😀", Some(1..6))]
+    #[case("😀", "abc", None)]
+    fn byte_offsets_case(
+        #[case] expr: &str,
+        #[case] line: &str,
+        #[case] expect: Option<std::ops::Range<usize>>,
+        #[values(true, false)] is_depth: bool,
+    ) {
+        let ast = parser::parse(expr).unwrap();
+        let code = codegen::get_code(&ast).unwrap();
+        let kind = MatchKind::LeftmostFirst;
+
+        assert_eq!(
+            eval_with_pattern(&code, line, 0, is_depth, kind).unwrap(),
+            expect
+        );
+    }
+
+    #[rstest]
+    #[case("a|b", "a")]
+    #[case("a|b", "b")]
+    #[case("a|b", "c")]
+    #[case("a|b|c", "c")]
+    #[case(".", "c")]
+    #[case(".d", "cd")]
+    #[case("abc|def", "def")]
+    #[case("abc|def", "efa")]
+    #[case("(ab|cd)+", "abcdcd")]
+    #[case("(ab|cd)+", "")]
+    #[case("abc?", "ab")]
+    #[case("abc?", "acd")]
+    #[case("^abc", "abcdef")]
+    #[case("^def", "abcdef")]
+    #[case("def$", "abcdef")]
+    #[case("abc$", "abcdef")]
+    #[case("a?a?aa", "aa")]
+    #[case("a?a?a?a?a?a?a?a?a?a?aaaaaaaaaa", "aaaaaaaaaa")]
+    #[case("ab.", "aabcabd")]
+    #[case("(ab)*", "cababc")]
+    #[case("(ab)*c?", "cababc")]
+    #[case("(?m)^b+$", "a\nbb\nc")]
+    #[case("(?mR)c$", "abc\r\nc")]
+    #[case(r"\Ab|c\z", "abc")]
+    #[case("(^a|b$)+", "abab")]
+    #[case("(b|^)+a", "bba")]
+    fn depth_width_agree(#[case] expr: &str, #[case] line: &str) {
+        assert_eq!(
+            do_matching_with_pattern(expr, line, true).unwrap(),
+            do_matching_with_pattern(expr, line, false).unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case("(a+)(b|(c))d", "xaabdy")]
+    #[case("(a+)(b|(c))d", "acd")]
+    #[case("(ab)*", "cababc")]
+    #[case("((a)|(b))+", "abba")]
+    #[case("(a|ab)(c|bcd)", "abcd")]
+    #[case("()a", "ba")]
+    #[case("(a+?)(a*)", "aaa")]
+    #[case("(a|ab)(c|bcd)(d*)", "abcd")]
+    fn depth_width_agree_on_captures(#[case] expr: &str, #[case] line: &str) {
+        let ast = parser::parse(expr).unwrap();
+        let code = codegen::get_code(&ast).unwrap();
+        for kind in [MatchKind::LeftmostFirst, MatchKind::LeftmostLongest] {
+            assert_eq!(
+                eval_with_captures(&code, line, 0, true, kind).unwrap(),
+                eval_with_captures(&code, line, 0, false, kind).unwrap()
+            );
+        }
+    }
+
+    #[rstest]
+    #[case("ab|abc", "xabc", "ab", "abc")]
+    #[case("a+?", "aaa", "a", "aaa")]
+    #[case("(a|ab)(c|b)?", "abcd", "ab", "abc")]
+    #[case("a|ab|abc", "abcd", "a", "abc")]
+    #[case("(ab|a)(bc|c)?", "abc", "abc", "abc")]
+    #[case("a*(b|bc)", "aabcd", "aab", "aabc")]
+    #[case("b|ab", "xab", "ab", "ab")]
+    fn match_kind_case(
+        #[case] expr: &str,
+        #[case] line: &str,
+        #[case] first: &str,
+        #[case] longest: &str,
+        #[values(true, false)] is_depth: bool,
+    ) {
+        let ast = parser::parse(expr).unwrap();
+        let code = codegen::get_code(&ast).unwrap();
+        for (kind, expect) in [
+            (MatchKind::LeftmostFirst, first),
+            (MatchKind::LeftmostLongest, longest),
+        ] {
+            let r = eval_with_pattern(&code, line, 0, is_depth, kind)
+                .unwrap()
+                .unwrap();
+            assert_eq!(&line[r], expect);
+        }
     }
 }
@@ -0,0 +1,927 @@
+use crate::engine::evaluator::{
+    eval, eval_shortest, eval_with_captures, group_range, EvalError, MatchKind,
+};
+use crate::engine::input::Input;
+use crate::engine::parser::PatternError;
+use crate::engine::{codegen, parser, Code};
+use crate::helper::DynError;
+use crate::replace::Replacer;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// A compiled regular expression.
+///
+/// The expression is parsed and compiled once by [`Regex::new`] and the program is reused by
+/// every match, so a single `Regex` can be shared between threads.
+#[derive(Debug)]
+pub struct Regex {
+    expr: String,
+    code: Code,
+    match_kind: MatchKind,
+    group_names: Arc<HashMap<String, usize>>,
+}
+
+impl Regex {
+    pub fn new(expr: &str) -> Result<Regex, DynError> {
+        RegexBuilder::new(expr).build()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.expr
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        expect_eval(eval(&self.code, line, self.code.requires_backtracking()))
+    }
+
+    /// Returns the byte range of the match in `line`.
+    pub fn find(&self, line: &str) -> Option<Range<usize>> {
+        let slots = self.search(line, 0)?;
+        group_range(&slots, 0)
+    }
+
+    /// Returns an iterator over the byte ranges of the successive non-overlapping matches.
+    pub fn find_iter<'r, 'h>(&'r self, line: &'h str) -> Matches<'r, 'h> {
+        Matches {
+            searcher: Searcher::new(self, line),
+        }
+    }
+
+    /// Returns an iterator over the substrings of `line` between successive matches.
+    ///
+    /// A match at the start or the end of `line` yields an empty substring there, and an empty
+    /// match splits between two chars.
+    pub fn split<'r, 'h>(&'r self, line: &'h str) -> Split<'r, 'h> {
+        Split {
+            matches: self.find_iter(line),
+            last_end: 0,
+            done: false,
+        }
+    }
+
+    /// Like [`Regex::split`], but yields at most `limit` substrings, the last of which holds the
+    /// rest of `line`.
+    pub fn splitn<'r, 'h>(&'r self, line: &'h str, limit: usize) -> SplitN<'r, 'h> {
+        SplitN {
+            split: self.split(line),
+            limit,
+        }
+    }
+
+    /// Returns the byte ranges of the match and of every capture group in it.
+    pub fn captures<'h>(&self, line: &'h str) -> Option<Captures<'h>> {
+        let slots = self.search(line, 0)?;
+        Some(self.new_captures(line, slots))
+    }
+
+    /// Returns an iterator over the captures of the successive non-overlapping matches.
+    pub fn captures_iter<'r, 'h>(&'r self, line: &'h str) -> CaptureMatches<'r, 'h> {
+        CaptureMatches {
+            searcher: Searcher::new(self, line),
+        }
+    }
+
+    /// Replaces the first match in `line` with `rep`.
+    pub fn replace<R: Replacer>(&self, line: &str, rep: R) -> String {
+        self.replacen(line, 1, rep)
+    }
+
+    /// Replaces every non-overlapping match in `line` with `rep`.
+    pub fn replace_all<R: Replacer>(&self, line: &str, rep: R) -> String {
+        self.replacen(line, 0, rep)
+    }
+
+    /// Replaces at most `limit` non-overlapping matches in `line` with `rep`. A `limit` of 0
+    /// replaces every match.
+    pub fn replacen<R: Replacer>(&self, line: &str, limit: usize, mut rep: R) -> String {
+        let mut dst = String::with_capacity(line.len());
+        let mut last_end = 0;
+
+        for (i, caps) in self.captures_iter(line).enumerate() {
+            if limit > 0 && i >= limit {
+                break;
+            }
+
+            let range = caps.get(0).expect("group 0 is always set in a match");
+            dst.push_str(&line[last_end..range.start]);
+            rep.replace_append(&caps, &mut dst);
+            last_end = range.end;
+        }
+        dst.push_str(&line[last_end..]);
+
+        dst
+    }
+
+    /// Number of capture groups, including group 0 for the whole match.
+    pub fn captures_len(&self) -> usize {
+        self.code.captures_len()
+    }
+
+    /// Returns the name of every capture group in index order, `None` for group 0 and for
+    /// groups without a name.
+    pub fn capture_names(&self) -> impl Iterator<Item = Option<&str>> + '_ {
+        self.code.capture_names().iter().map(|name| name.as_deref())
+    }
+
+    /// Returns the byte offset where the earliest-ending match ends. Patterns with
+    /// backreferences or lookaround cannot run on the engine that finds it, and report where
+    /// the match of [`Regex::find`] ends instead.
+    pub fn shortest_match(&self, line: &str) -> Option<usize> {
+        if self.code.requires_backtracking() {
+            return self.find(line).map(|r| r.end);
+        }
+
+        expect_eval(eval_shortest(&self.code, line))
+    }
+
+    fn new_captures<'h>(&self, line: &'h str, slots: Vec<Option<usize>>) -> Captures<'h> {
+        Captures {
+            line,
+            slots,
+            group_names: self.group_names.clone(),
+        }
+    }
+
+    /// Returns the capture slots of the first match at or after byte `start`. Only patterns
+    /// with backreferences or lookaround run on the backtracker, whose memory grows with the
+    /// length of `line` times the size of the program.
+    pub(crate) fn search<I: Input + ?Sized>(
+        &self,
+        line: &I,
+        start: usize,
+    ) -> Option<Vec<Option<usize>>> {
+        expect_eval(eval_with_captures(
+            &self.code,
+            line,
+            start,
+            self.code.requires_backtracking(),
+            self.match_kind,
+        ))
+    }
+}
+
+/// Configures how a [`Regex`] is compiled.
+#[derive(Debug, Clone)]
+pub struct RegexBuilder {
+    expr: String,
+    size_limit: usize,
+    match_kind: MatchKind,
+    flags: parser::Flags,
+}
+
+impl RegexBuilder {
+    pub fn new(expr: &str) -> Self {
+        Self {
+            expr: expr.to_string(),
+            size_limit: codegen::DEFAULT_SIZE_LIMIT,
+            match_kind: MatchKind::default(),
+            flags: parser::Flags::default(),
+        }
+    }
+
+    /// Sets the maximum size of the compiled program, counted as its instructions plus the
+    /// ranges of its classes.
+    pub fn size_limit(&mut self, limit: usize) -> &mut Self {
+        self.size_limit = limit;
+        self
+    }
+
+    /// Sets which match `find` and `captures` report, leftmost-first by default.
+    pub fn match_kind(&mut self, kind: MatchKind) -> &mut Self {
+        self.match_kind = kind;
+        self
+    }
+
+    /// Makes `^` and `$` match at the start and end of every line, as `(?m)` does.
+    pub fn multi_line(&mut self, yes: bool) -> &mut Self {
+        self.flags.multi_line = yes;
+        self
+    }
+
+    /// Makes letters match regardless of case, as `(?i)` does.
+    pub fn case_insensitive(&mut self, yes: bool) -> &mut Self {
+        self.flags.case_insensitive = yes;
+        self
+    }
+
+    /// Makes `.` also match `\n`, as `(?s)` does.
+    pub fn dot_all(&mut self, yes: bool) -> &mut Self {
+        self.flags.dot_all = yes;
+        self
+    }
+
+    /// Lets lines also end with `\r\n` in multi-line mode, as `(?R)` does.
+    pub fn crlf(&mut self, yes: bool) -> &mut Self {
+        self.flags.crlf = yes;
+        self
+    }
+
+    pub fn build(&self) -> Result<Regex, DynError> {
+        let ast = parser::parse_with_flags(&self.expr, self.flags)
+            .map_err(|e| PatternError::new(&self.expr, e))?;
+        let code = codegen::get_code_with_limit(&ast, self.size_limit)?;
+        let group_names = code
+            .capture_names()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, name)| Some((name.clone()?, i)))
+            .collect();
+
+        Ok(Regex {
+            expr: self.expr.clone(),
+            code,
+            match_kind: self.match_kind,
+            group_names: Arc::new(group_names),
+        })
+    }
+}
+
+/// The byte ranges of the capture groups of one match. Group 0 is the whole match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captures<'h> {
+    line: &'h str,
+    slots: Vec<Option<usize>>,
+    group_names: Arc<HashMap<String, usize>>,
+}
+
+#[allow(clippy::len_without_is_empty)]
+impl<'h> Captures<'h> {
+    /// Returns the range of group `i`, or `None` if the group did not take part in the match.
+    pub fn get(&self, i: usize) -> Option<Range<usize>> {
+        group_range(&self.slots, i)
+    }
+
+    /// Returns the range of the group named `name`, or `None` if there is no such group or it
+    /// did not take part in the match.
+    pub fn name(&self, name: &str) -> Option<Range<usize>> {
+        self.get(self.group_index(name)?)
+    }
+
+    pub(crate) fn group_index(&self, name: &str) -> Option<usize> {
+        self.group_names.get(name).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Option<Range<usize>>> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+
+    /// Returns the text matched by group `i`.
+    pub fn as_str(&self, i: usize) -> Option<&'h str> {
+        self.get(i).map(|r| &self.line[r])
+    }
+
+    /// Appends `template` to `dst`, replacing `$1`, `${1}` and `${name}` with the text of the
+    /// group, and `$$` with a literal `$`. Groups that did not match expand to nothing.
+    pub fn expand(&self, template: &str, dst: &mut String) {
+        crate::replace::expand(self, template, dst)
+    }
+}
+
+/// Finds successive non-overlapping matches. After an empty match the search moves on by one
+/// char, and an empty match right where the previous match ended is skipped.
+pub(crate) struct Searcher<'r, 'h, H: Input + ?Sized> {
+    regex: &'r Regex,
+    line: &'h H,
+    last_end: usize,
+    last_match: Option<usize>,
+}
+
+impl<'r, 'h, H: Input + ?Sized> Searcher<'r, 'h, H> {
+    pub(crate) fn new(regex: &'r Regex, line: &'h H) -> Self {
+        Self {
+            regex,
+            line,
+            last_end: 0,
+            last_match: None,
+        }
+    }
+
+    pub(crate) fn next_slots(&mut self) -> Option<Vec<Option<usize>>> {
+        loop {
+            let slots = self.regex.search(self.line, self.last_end)?;
+            let range = group_range(&slots, 0)?;
+
+            if range.is_empty() {
+                let len = self.line.char_at(range.end).map_or(1, |(_, len)| len);
+                self.last_end = range.end + len;
+                if self.last_match == Some(range.end) {
+                    continue;
+                }
+            } else {
+                self.last_end = range.end;
+            }
+            self.last_match = Some(range.end);
+
+            return Some(slots);
+        }
+    }
+}
+
+/// An iterator over the byte ranges of successive non-overlapping matches in a line.
+pub struct Matches<'r, 'h> {
+    searcher: Searcher<'r, 'h, str>,
+}
+
+impl Iterator for Matches<'_, '_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Range<usize>> {
+        let slots = self.searcher.next_slots()?;
+        group_range(&slots, 0)
+    }
+}
+
+/// An iterator over the substrings between successive matches in a line.
+pub struct Split<'r, 'h> {
+    matches: Matches<'r, 'h>,
+    last_end: usize,
+    done: bool,
+}
+
+impl<'h> Split<'_, 'h> {
+    fn rest(&mut self) -> Option<&'h str> {
+        if self.done {
+            return None;
+        }
+        self.done = true;
+
+        Some(&self.matches.searcher.line[self.last_end..])
+    }
+}
+
+impl<'h> Iterator for Split<'_, 'h> {
+    type Item = &'h str;
+
+    fn next(&mut self) -> Option<&'h str> {
+        if self.done {
+            return None;
+        }
+
+        match self.matches.next() {
+            Some(range) => {
+                let piece = &self.matches.searcher.line[self.last_end..range.start];
+                self.last_end = range.end;
+                Some(piece)
+            }
+            None => self.rest(),
+        }
+    }
+}
+
+/// An iterator over at most a fixed number of substrings between matches in a line.
+pub struct SplitN<'r, 'h> {
+    split: Split<'r, 'h>,
+    limit: usize,
+}
+
+impl<'h> Iterator for SplitN<'_, 'h> {
+    type Item = &'h str;
+
+    fn next(&mut self) -> Option<&'h str> {
+        if self.limit == 0 {
+            return None;
+        }
+        self.limit -= 1;
+
+        if self.limit == 0 {
+            self.split.rest()
+        } else {
+            self.split.next()
+        }
+    }
+}
+
+/// An iterator over the captures of successive non-overlapping matches in a line.
+pub struct CaptureMatches<'r, 'h> {
+    searcher: Searcher<'r, 'h, str>,
+}
+
+impl<'h> Iterator for CaptureMatches<'_, 'h> {
+    type Item = Captures<'h>;
+
+    fn next(&mut self) -> Option<Captures<'h>> {
+        let slots = self.searcher.next_slots()?;
+        Some(self.searcher.regex.new_captures(self.searcher.line, slots))
+    }
+}
+
+pub(crate) fn expect_eval<T>(result: Result<T, EvalError>) -> T {
+    result.unwrap_or_else(|e| panic!("evaluation of a compiled program failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::parser::ParserError;
+    use crate::replace::NoExpand;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("a|b", "b", true)]
+    #[case("abc?", "ab", true)]
+    #[case("^def", "abcdef", false)]
+    #[case("(ab|cd)+", "", false)]
+    fn is_match(#[case] expr: &str, #[case] line: &str, #[case] expect: bool) {
+        let re = Regex::new(expr).unwrap();
+        assert_eq!(re.is_match(line), expect);
+    }
+
+    #[test]
+    fn reuse_compiled() {
+        let re = Regex::new("ab.").unwrap();
+
+        assert_eq!(re.find("aabcabd"), Some(1..4));
+        assert_eq!(re.find("abd"), Some(0..3));
+        assert_eq!(re.find("xyz"), None);
+        assert_eq!(re.as_str(), "ab.");
+    }
+
+    #[test]
+    fn byte_offsets() {
+        let re = Regex::new("b.").unwrap();
+
+        let line = "ăbçd";
+        let r = re.find(line).unwrap();
+        assert_eq!(r, 2..5);
+        assert_eq!(&line[r], "bç");
+    }
+
+    #[test]
+    fn shortest_match() {
+        let re = Regex::new("a+").unwrap();
+
+        assert_eq!(re.shortest_match("xaaa"), Some(2));
+        assert_eq!(re.shortest_match("xyz"), None);
+    }
+
+    #[test]
+    fn captures() {
+        let re = Regex::new("(a+)(b|(c))d").unwrap();
+        assert_eq!(re.captures_len(), 4);
+
+        let caps = re.captures("xaabdy").unwrap();
+        assert_eq!(caps.len(), 4);
+        assert_eq!(caps.get(0), Some(1..5));
+        assert_eq!(caps.get(1), Some(1..3));
+        assert_eq!(caps.get(2), Some(3..4));
+        assert_eq!(caps.get(3), None);
+        assert_eq!(caps.get(4), None);
+
+        let caps = re.captures("acd").unwrap();
+        assert_eq!(
+            caps.iter().collect::<Vec<_>>(),
+            vec![Some(0..3), Some(0..1), Some(1..2), Some(1..2)]
+        );
+
+        assert_eq!(re.captures("abc"), None);
+    }
+
+    #[test]
+    fn named_captures() {
+        let re = Regex::new(r"(?P<key>\w+)=(?:(?<num>\d+)|(\w+))").unwrap();
+        assert_eq!(re.captures_len(), 4);
+        assert_eq!(
+            re.capture_names().collect::<Vec<_>>(),
+            vec![None, Some("key"), Some("num"), None]
+        );
+
+        let caps = re.captures("id=42").unwrap();
+        assert_eq!(caps.name("key"), Some(0..2));
+        assert_eq!(caps.name("num"), Some(3..5));
+        assert_eq!(caps.get(3), None);
+        assert_eq!(caps.name("other"), None);
+
+        let caps = re.captures("id=x").unwrap();
+        assert_eq!(caps.name("num"), None);
+        assert_eq!(caps.get(3), Some(3..4));
+
+        assert_eq!(re.replace_all("a=1 b=c", "${num}:$key"), "1:a :b");
+    }
+
+    #[rstest]
+    #[case(r"(\w+) \1", "to be be or", Some(3..8))]
+    #[case(r"(a|b)\1", "ab bb", Some(3..5))]
+    #[case(r"(a)?b\1", "b", None)]
+    #[case(r"(a*)\1", "aaa", Some(0..2))]
+    #[case(r"^(a+)\1$", "aaaaaa", Some(0..6))]
+    #[case(r"^(a+)\1$", "aaaaa", None)]
+    #[case(r"(?<c>.)\k<c>", "xyzz", Some(2..4))]
+    #[case(r"(é)\1", "aéé", Some(1..5))]
+    #[case(r"(a*)*b\1", "aab", Some(0..3))]
+    #[case(r"((a*)*|b)\2c", "bc", Some(1..2))]
+    #[case(r"(?i)(a)\1", "aA", Some(0..2))]
+    #[case(r"(a)\1", "aA", None)]
+    #[case(r"(a)(?i)\1", "aA", Some(0..2))]
+    #[case(r"(?i:(a))\1", "aA", None)]
+    #[case(r"(?i)(é)\1", "éÉ", Some(0..4))]
+    fn backref(#[case] expr: &str, #[case] line: &str, #[case] expect: Option<Range<usize>>) {
+        let re = Regex::new(expr).unwrap();
+        assert_eq!(re.find(line), expect);
+        assert_eq!(re.shortest_match(line), expect.map(|r| r.end));
+    }
+
+    #[rstest]
+    #[case("[a-z&&[^aeiou]]+", "aeiou xyz", Some(6..9))]
+    #[case(r"[\p{L}--\p{Latin}]+", "abc αβγ", Some(4..10))]
+    #[case(r"[\w&&\D]+", "12ab3", Some(2..4))]
+    #[case("[[a-c][x-z]]+", "dcxb", Some(1..4))]
+    #[case("(?i)[^a]", "Aab", Some(2..3))]
+    fn class_set(#[case] expr: &str, #[case] line: &str, #[case] expect: Option<Range<usize>>) {
+        assert_eq!(Regex::new(expr).unwrap().find(line), expect);
+    }
+
+    #[rstest]
+    #[case("[[:alpha:]]+", "12 abc 3", Some(3..6))]
+    #[case("[[:alnum:]_]+", "-a_1-", Some(1..4))]
+    #[case("[[:^space:]]+", "  foo bar", Some(2..5))]
+    #[case("[[:punct:]]", "ab, c", Some(2..3))]
+    #[case("[[:xdigit:]]{2}", "0xfg 1A", Some(5..7))]
+    #[case("[[:cntrl:]]", "a\x7F", Some(1..2))]
+    #[case("[[:print:]]+", "\tab c\n", Some(1..5))]
+    #[case("[[:graph:]]+", "\tab c\n", Some(1..3))]
+    #[case("(?i)[[:lower:]]+", "ABC", Some(0..3))]
+    #[case("[[:upper:][:digit:]]+", "aB1c", Some(1..3))]
+    fn posix_class(#[case] expr: &str, #[case] line: &str, #[case] expect: Option<Range<usize>>) {
+        assert_eq!(Regex::new(expr).unwrap().find(line), expect);
+    }
+
+    #[rstest]
+    #[case(r"\p{Greek}+", "alpha αβγ beta", Some(6..12))]
+    #[case(r"\p{Lu}\p{Ll}+", "über Straße", Some(6..13))]
+    #[case(r"[\p{Han}\p{Hiragana}]+", "東京へ行く!", Some(0..15))]
+    #[case(r"\P{L}+", "ab 12 cd", Some(2..6))]
+    #[case(r"\pN", "x٣", Some(1..3))]
+    #[case(r"(?i)\p{Lu}", "é", Some(0..2))]
+    fn unicode_property(
+        #[case] expr: &str,
+        #[case] line: &str,
+        #[case] expect: Option<Range<usize>>,
+    ) {
+        assert_eq!(Regex::new(expr).unwrap().find(line), expect);
+    }
+
+    #[rstest]
+    #[case(r"\t", "a\tb", Some(1..2))]
+    #[case(r"[^\t\n]+\n", "x\tyz\n", Some(2..5))]
+    #[case(r"\x{1F600}+", "hi 😀😀!", Some(3..11))]
+    #[case(r"\u{e9}", "caf\u{e9}", Some(3..5))]
+    #[case(r"[\x41-\x43]+", "xxBCAD", Some(2..5))]
+    #[case(r"(?i)\x41", "a", Some(0..1))]
+    fn char_escape(#[case] expr: &str, #[case] line: &str, #[case] expect: Option<Range<usize>>) {
+        assert_eq!(Regex::new(expr).unwrap().find(line), expect);
+    }
+
+    #[rstest]
+    #[case(r"^(?=.*\d)(?=.*[a-z]).{8,}$", "abcdefg1", Some(0..8))]
+    #[case(r"^(?=.*\d)(?=.*[a-z]).{8,}$", "abcdefgh", None)]
+    #[case(r"^(?=.*\d)(?=.*[a-z]).{8,}$", "1234567", None)]
+    #[case("foo(?!bar)", "foobar foobaz", Some(7..10))]
+    #[case(r"(?<=\$)\d+", "cost $42", Some(6..8))]
+    #[case(r"(?<!-)\b\d+", "-5 7", Some(3..4))]
+    #[case("(?<=é)x", "éx", Some(2..3))]
+    #[case("(?<=ab|c)d", "xd abd", Some(5..6))]
+    #[case("(?<=^a)b", "bab ab", None)]
+    #[case("(?<=^a)b", "abab", Some(1..2))]
+    #[case("a(?=b(?<=ab))", "ac ab", Some(3..4))]
+    #[case("(?=(a+))a*b", "aab", Some(0..3))]
+    fn lookaround(#[case] expr: &str, #[case] line: &str, #[case] expect: Option<Range<usize>>) {
+        let re = Regex::new(expr).unwrap();
+        assert_eq!(re.find(line), expect);
+        assert_eq!(re.shortest_match(line), expect.map(|r| r.end));
+    }
+
+    #[test]
+    fn lookaround_iter() {
+        let re = Regex::new(r"(?<=^|,)\w").unwrap();
+        assert_eq!(
+            re.find_iter("a,b,,c").collect::<Vec<_>>(),
+            vec![0..1, 2..3, 5..6]
+        );
+
+        let re = Regex::new(r"(?=(\d))").unwrap();
+        let caps = re.captures("x1").unwrap();
+        assert_eq!(caps.get(0), Some(1..1));
+        assert_eq!(caps.get(1), Some(1..2));
+
+        let re = Regex::new(r"(?<=(\d))x").unwrap();
+        assert_eq!(re.captures("1x").unwrap().get(1), Some(0..1));
+
+        let re = Regex::new(r"(?!(\d))\w").unwrap();
+        assert_eq!(re.captures("1x").unwrap().get(1), None);
+
+        let re = Regex::new(r"(?=(a))\1").unwrap();
+        assert_eq!(re.find("aa"), Some(0..1));
+
+        let re = Regex::new(r"(?=(a+))\1a").unwrap();
+        assert_eq!(re.find("aaa"), None);
+        assert_eq!(re.find("aab"), None);
+    }
+
+    #[test]
+    fn parse_error_message() {
+        let err = Regex::new("(a|b))").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "regex parse error: unopened group\n    (a|b))\n         ^ this `)` closes no group\nhelp: escape it as `\\)` to match a literal `)`"
+        );
+
+        let err = err.downcast::<PatternError>().unwrap();
+        assert!(matches!(err.error(), ParserError::UnmatchedRightParen(span) if *span == (5..6)));
+    }
+
+    #[test]
+    fn captures_byte_offsets() {
+        let re = Regex::new("(é+)(.)").unwrap();
+        let line = "aééb";
+        let caps = re.captures(line).unwrap();
+        assert_eq!(&line[caps.get(1).unwrap()], "éé");
+        assert_eq!(&line[caps.get(2).unwrap()], "b");
+    }
+
+    #[test]
+    fn size_limit() {
+        assert!(Regex::new("a{1000}{1000}").is_err());
+        assert!(RegexBuilder::new("a{1000}{1000}")
+            .size_limit(2_000_000)
+            .build()
+            .is_ok());
+
+        let err = RegexBuilder::new("[a-z]{20}")
+            .size_limit(10)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "compiled program exceeds the size limit of 10"
+        );
+    }
+
+    #[rstest]
+    #[case("ab|abc", "abc", 0..2, 0..3)]
+    #[case("a*?", "aaa", 0..0, 0..3)]
+    #[case("(a|ab)(c|b)?", "abcd", 0..2, 0..3)]
+    #[case("x(a+|b)*", "zxaab", 1..5, 1..5)]
+    fn match_kind(
+        #[case] expr: &str,
+        #[case] line: &str,
+        #[case] first: Range<usize>,
+        #[case] longest: Range<usize>,
+    ) {
+        let re = RegexBuilder::new(expr).build().unwrap();
+        assert_eq!(re.find(line), Some(first));
+
+        let re = RegexBuilder::new(expr)
+            .match_kind(MatchKind::LeftmostLongest)
+            .build()
+            .unwrap();
+        assert_eq!(re.find(line), Some(longest));
+    }
+
+    #[test]
+    fn find_iter_long_line() {
+        let line = "a ".repeat(200_000);
+        let re = Regex::new("a").unwrap();
+        assert_eq!(re.find_iter(&line).count(), 200_000);
+        assert_eq!(re.replace_all(&line, "b"), "b ".repeat(200_000));
+        assert_eq!(re.split(&line).count(), 200_001);
+    }
+
+    #[test]
+    fn leftmost_longest_long_line() {
+        let line = "aa ".repeat(20_000);
+        for expr in ["a+", r"(a)\1"] {
+            let re = RegexBuilder::new(expr)
+                .match_kind(MatchKind::LeftmostLongest)
+                .build()
+                .unwrap();
+            assert_eq!(re.find_iter(&line).count(), 20_000);
+        }
+    }
+
+    #[rstest]
+    #[case("(?m)^\\w+$", "ab\ncd\n", vec![0..2, 3..5])]
+    #[case("(?m)^", "a\n\nb", vec![0..0, 2..2, 3..3])]
+    #[case("(?m)$", "a\nb\n", vec![1..1, 3..3, 4..4])]
+    #[case("(?m)b$", "ab\r\nb", vec![4..5])]
+    #[case("(?mR)b$", "ab\r\nb", vec![1..2, 4..5])]
+    #[case("(?mR)^b", "ab\r\nb\rb", vec![4..5, 6..7])]
+    #[case("(?mR)$", "a\r\n", vec![1..1, 3..3])]
+    #[case(r"(?m)\Aa", "a\na", vec![0..1])]
+    #[case(r"(?m)a\z", "a\na", vec![2..3])]
+    #[case(r"(?m)a\z", "a\n", vec![])]
+    fn multi_line(#[case] expr: &str, #[case] line: &str, #[case] expect: Vec<Range<usize>>) {
+        let re = Regex::new(expr).unwrap();
+        assert_eq!(re.find_iter(line).collect::<Vec<_>>(), expect);
+    }
+
+    #[test]
+    fn multi_line_option() {
+        let line = "k=1\r\nv=2\r\n";
+
+        let re = RegexBuilder::new(r"^\w=\d$")
+            .multi_line(true)
+            .build()
+            .unwrap();
+        assert_eq!(re.find_iter(line).count(), 0);
+
+        let re = RegexBuilder::new(r"^\w=\d$")
+            .multi_line(true)
+            .crlf(true)
+            .build()
+            .unwrap();
+        assert_eq!(re.find_iter(line).collect::<Vec<_>>(), vec![0..3, 5..8]);
+
+        let re = RegexBuilder::new(r"(?-m)\w=\d$")
+            .multi_line(true)
+            .build()
+            .unwrap();
+        assert!(!re.is_match(line));
+    }
+
+    #[rstest]
+    #[case("(?i)hello", "say HeLLo", Some(4..9))]
+    #[case("(?i)straße", "STRASSE STRAẞE", Some(8..16))]
+    #[case("(?i)σ+", "ΣσςX", Some(0..6))]
+    #[case("(?i)k", "\u{212A}", Some(0..3))]
+    #[case("(?i)[a-c]+", "xAbC", Some(1..4))]
+    #[case("(?i)[^a]", "Ab", Some(1..2))]
+    #[case("a(?i:b)c", "aBc ABC", Some(0..3))]
+    #[case("a(?i:b)c", "ABC", None)]
+    #[case("(?i)a(?-i)b", "Ab AB", Some(0..2))]
+    fn case_insensitive(
+        #[case] expr: &str,
+        #[case] line: &str,
+        #[case] expect: Option<Range<usize>>,
+    ) {
+        let re = Regex::new(expr).unwrap();
+        assert_eq!(re.find(line), expect);
+    }
+
+    #[test]
+    fn case_insensitive_option() {
+        let re = RegexBuilder::new("error: (ab)+")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        assert_eq!(re.find("ERROR: aBAb"), Some(0..11));
+
+        let re = RegexBuilder::new("x(?-i:y)")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        assert!(re.is_match("Xy"));
+        assert!(!re.is_match("XY"));
+    }
+
+    #[rstest]
+    #[case("a.*", "ab\ncd", Some(0..2))]
+    #[case("(?s)a.*", "ab\ncd", Some(0..5))]
+    #[case("a.c", "a\nc", None)]
+    #[case("(?s:a.)c.", "a\nc\n", None)]
+    #[case("(?s:a.)c.", "a\ncd", Some(0..4))]
+    #[case(".", "\r", Some(0..1))]
+    fn dot_all(#[case] expr: &str, #[case] line: &str, #[case] expect: Option<Range<usize>>) {
+        let re = Regex::new(expr).unwrap();
+        assert_eq!(re.find(line), expect);
+    }
+
+    #[test]
+    fn dot_all_option() {
+        let re = RegexBuilder::new("<.+>").dot_all(true).build().unwrap();
+        assert_eq!(re.find("<a\nb>"), Some(0..5));
+
+        let re = RegexBuilder::new("<(?-s:.+)>")
+            .dot_all(true)
+            .build()
+            .unwrap();
+        assert_eq!(re.find("<a\nb>"), None);
+    }
+
+    #[rstest]
+    #[case("a+", "aabaaab", vec![0..2, 3..6])]
+    #[case("a*", "baaa", vec![0..0, 1..4])]
+    #[case("a*", "", vec![0..0])]
+    #[case("()", "abc", vec![0..0, 1..1, 2..2, 3..3])]
+    #[case("x*", "aé", vec![0..0, 1..1, 3..3])]
+    #[case(r"\b", "ab cd", vec![0..0, 2..2, 3..3, 5..5])]
+    #[case("^a", "aaa", vec![0..1])]
+    #[case("a|", "ba", vec![0..0, 1..2])]
+    #[case("(a|)b", "b ab", vec![0..1, 2..4])]
+    #[case("z", "abc", vec![])]
+    fn find_iter(#[case] expr: &str, #[case] line: &str, #[case] expect: Vec<Range<usize>>) {
+        let re = Regex::new(expr).unwrap();
+        assert_eq!(re.find_iter(line).collect::<Vec<_>>(), expect);
+    }
+
+    #[test]
+    fn captures_iter() {
+        let re = Regex::new(r"(\w+)=(\d*)").unwrap();
+        let pairs: Vec<_> = re
+            .captures_iter("a=1, bc=, d=23")
+            .map(|caps| (caps.as_str(1).unwrap(), caps.as_str(2).unwrap()))
+            .collect();
+        assert_eq!(pairs, vec![("a", "1"), ("bc", ""), ("d", "23")]);
+    }
+
+    #[rstest]
+    #[case("a", "banana", "o", "bonana", "bonono")]
+    #[case(r"(\w+)@(\w+)", "x@y z@w", "$2@$1", "y@x z@w", "y@x w@z")]
+    #[case(r"(\w+)", "ab cd", "${1}_", "ab_ cd", "ab_ cd_")]
+    #[case(r"\d", "a1b2", "$$", "a$b2", "a$b$")]
+    #[case("(a)|b", "ab", "[$1]", "[a]b", "[a][]")]
+    #[case("x*", "ab", "-", "-ab", "-a-b-")]
+    #[case("z", "abc", "y", "abc", "abc")]
+    fn replace(
+        #[case] expr: &str,
+        #[case] line: &str,
+        #[case] template: &str,
+        #[case] first: &str,
+        #[case] all: &str,
+    ) {
+        let re = Regex::new(expr).unwrap();
+        assert_eq!(re.replace(line, template), first);
+        assert_eq!(re.replace_all(line, template), all);
+    }
+
+    #[test]
+    fn replacen() {
+        let re = Regex::new(r"\d").unwrap();
+        assert_eq!(re.replacen("1234", 2, "x"), "xx34");
+        assert_eq!(re.replacen("1234", 0, "x"), "xxxx");
+        assert_eq!(re.replacen("12", 5, "x"), "xx");
+    }
+
+    #[test]
+    fn replace_with_closure() {
+        let re = Regex::new(r"(\d+)").unwrap();
+        let doubled = re.replace_all("a1 b20", |caps: &Captures| {
+            let n: usize = caps.as_str(1).unwrap().parse().unwrap();
+            (n * 2).to_string()
+        });
+        assert_eq!(doubled, "a2 b40");
+
+        let mut count = 0;
+        let numbered = re.replace_all("x y", |_: &Captures| {
+            count += 1;
+            count.to_string()
+        });
+        assert_eq!(numbered, "x y");
+        assert_eq!(re.replace_all("7 7", NoExpand("$1")), "$1 $1");
+    }
+
+    #[rstest]
+    #[case(", *", "a, b,c,   d", vec!["a", "b", "c", "d"])]
+    #[case(",", ",a,,b,", vec!["", "a", "", "b", ""])]
+    #[case(",", "", vec![""])]
+    #[case(",", "abc", vec!["abc"])]
+    #[case("x*", "ab", vec!["", "a", "b", ""])]
+    #[case(r"\s+", "  é  ü ", vec!["", "é", "ü", ""])]
+    fn split(#[case] expr: &str, #[case] line: &str, #[case] expect: Vec<&str>) {
+        let re = Regex::new(expr).unwrap();
+        assert_eq!(re.split(line).collect::<Vec<_>>(), expect);
+    }
+
+    #[rstest]
+    #[case(", *", "a, b,c,   d", 0, vec![])]
+    #[case(", *", "a, b,c,   d", 1, vec!["a, b,c,   d"])]
+    #[case(", *", "a, b,c,   d", 2, vec!["a", "b,c,   d"])]
+    #[case(", *", "a, b,c,   d", 10, vec!["a", "b", "c", "d"])]
+    #[case(",", "a,", 2, vec!["a", ""])]
+    #[case(",", "", 3, vec![""])]
+    fn splitn(
+        #[case] expr: &str,
+        #[case] line: &str,
+        #[case] limit: usize,
+        #[case] expect: Vec<&str>,
+    ) {
+        let re = Regex::new(expr).unwrap();
+        assert_eq!(re.splitn(line, limit).collect::<Vec<_>>(), expect);
+    }
+
+    #[test]
+    fn parse_error() {
+        assert!(Regex::new("*a").is_err());
+    }
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Regex>();
+
+        let re = std::sync::Arc::new(Regex::new("a|b").unwrap());
+        let handles: Vec<_> = ["a", "b", "c"]
+            .into_iter()
+            .map(|line| {
+                let re = re.clone();
+                std::thread::spawn(move || re.is_match(line))
+            })
+            .collect();
+        let results: Vec<bool> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results, vec![true, true, false]);
+    }
+}
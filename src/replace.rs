@@ -0,0 +1,116 @@
+use crate::regex::Captures;
+
+/// Produces the replacement text for each match found by `Regex::replace` and friends.
+///
+/// It is implemented for template strings, which are expanded with [`Captures::expand`], and
+/// for closures that compute the replacement from the captures of the match.
+pub trait Replacer {
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String);
+}
+
+impl Replacer for &str {
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        caps.expand(self, dst);
+    }
+}
+
+impl Replacer for &String {
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        caps.expand(self, dst);
+    }
+}
+
+impl Replacer for String {
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        caps.expand(self, dst);
+    }
+}
+
+impl<F, T> Replacer for F
+where
+    F: FnMut(&Captures<'_>) -> T,
+    T: AsRef<str>,
+{
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        dst.push_str(self(caps).as_ref());
+    }
+}
+
+/// A replacement inserted as is, without expanding `$` references.
+#[derive(Debug, Clone, Copy)]
+pub struct NoExpand<'t>(pub &'t str);
+
+impl Replacer for NoExpand<'_> {
+    fn replace_append(&mut self, _: &Captures<'_>, dst: &mut String) {
+        dst.push_str(self.0);
+    }
+}
+
+/// Reads the group reference right after a `$`, either `{...}` or a run of word chars.
+/// Returns the reference and the rest of the template.
+fn parse_ref(rest: &str) -> Option<(&str, &str)> {
+    if let Some(braced) = rest.strip_prefix('{') {
+        let close = braced.find('}')?;
+        return Some((&braced[..close], &braced[close + 1..]));
+    }
+
+    let len = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    if len == 0 {
+        return None;
+    }
+
+    Some((&rest[..len], &rest[len..]))
+}
+
+pub(crate) fn expand(caps: &Captures<'_>, template: &str, dst: &mut String) {
+    let mut rest = template;
+
+    while let Some(i) = rest.find('$') {
+        dst.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            dst.push('$');
+            rest = after;
+            continue;
+        }
+
+        match parse_ref(rest) {
+            Some((name, after)) => {
+                let index = name.parse().ok().or_else(|| caps.group_index(name));
+                if let Some(text) = index.and_then(|i| caps.as_str(i)) {
+                    dst.push_str(text);
+                }
+                rest = after;
+            }
+            None => dst.push('$'),
+        }
+    }
+    dst.push_str(rest);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Regex;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("<$1>", "<ab>")]
+    #[case("$2$1", "cab")]
+    #[case("$1x", "")]
+    #[case("${1}x", "abx")]
+    #[case("${1", "${1")]
+    #[case("$ $", "$ $")]
+    #[case("$$1", "$1")]
+    #[case("$9", "")]
+    #[case("${name}", "")]
+    fn expand(#[case] template: &str, #[case] expect: &str) {
+        let caps = Regex::new("(ab)(c)").unwrap().captures("abc").unwrap();
+
+        let mut dst = String::new();
+        caps.expand(template, &mut dst);
+        assert_eq!(dst, expect);
+    }
+}